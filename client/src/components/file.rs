@@ -1,73 +1,87 @@
-use gloo_console::log;
-use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
-use wasm_bindgen_futures::spawn_local;
-use web_sys::{
-    HtmlDivElement, IntersectionObserver, IntersectionObserverEntry, IntersectionObserverInit,
-};
+use std::rc::Rc;
+
+use gloo::file::{Blob, ObjectUrl};
 use yew::prelude::*;
+use yew_icons::{Icon, IconId};
 use yewdux::prelude::use_store;
 
-use crate::store::{load_item, Store};
+use crate::{
+    store::{open_folder, set_starred, EntryKind, FileEntry, Store},
+    utils::send_get_bytes,
+};
 
 #[derive(Properties, PartialEq)]
 pub struct FileProps {
-    pub name: String,
+    pub entry: FileEntry,
 }
 
 #[function_component(File)]
 pub fn file(props: &FileProps) -> Html {
     let (_, dispatch) = use_store::<Store>();
-    let name = props.name.clone();
-    let div_ref = use_node_ref();
-    let item_name = use_state(|| String::new());
-    let name_state = item_name.clone();
+    let thumbnail = use_state(|| None::<Rc<ObjectUrl>>);
 
     {
-        let div = div_ref.clone();
-        use_effect(move || {
-            let options = IntersectionObserverInit::new();
-
-            let div = div.cast::<HtmlDivElement>().expect("Div not set");
-
-            let callback = Closure::wrap(Box::new(
-                move |entries: Vec<JsValue>, _observer: IntersectionObserver| {
-                    for entry in entries {
-                        let entry = IntersectionObserverEntry::from(entry);
-                        let is_intersecting = entry.is_intersecting();
-
-                        let clone = name.clone();
-                        let dispatch_clone = dispatch.clone();
-                        spawn_local(async move {
-                            if is_intersecting {
-                                let res =
-                                    load_item(clone.parse::<u32>().unwrap(), dispatch_clone).await;
-                                match res {
-                                    Ok(res) => name_state.set(res.0),
-                                    Err(_) => {}
-                                };
-                            }
-                        });
-                    }
-                },
-            )
-                as Box<dyn FnMut(Vec<JsValue>, IntersectionObserver)>);
-
-            let observer =
-                IntersectionObserver::new_with_options(callback.as_ref().unchecked_ref(), &options)
-                    .unwrap();
+        let thumbnail = thumbnail.clone();
+        use_effect_with_deps(
+            move |(id, mime): &(String, String)| {
+                thumbnail.set(None);
+                if mime.starts_with("image/") {
+                    let url = format!("/api/thumbnail/{}?size=medium", id);
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Ok(bytes) = send_get_bytes(&url).await {
+                            let blob = Blob::new_with_options(bytes.as_slice(), Some("image/png"));
+                            thumbnail.set(Some(Rc::new(ObjectUrl::from(blob))));
+                        }
+                    });
+                }
+                || ()
+            },
+            (props.entry.id.clone(), props.entry.mime.clone()),
+        );
+    }
 
-            observer.observe(&div);
+    let on_star = {
+        let entry = props.entry.clone();
+        let dispatch = dispatch.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.stop_propagation();
+            let id = entry.id.clone();
+            let starred = !entry.starred;
+            let dispatch = dispatch.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                set_starred(id, starred, dispatch).await;
+            });
+        })
+    };
 
-            move || {
-                callback.forget();
-                observer.unobserve(&div);
+    let onclick = {
+        let entry = props.entry.clone();
+        Callback::from(move |_: MouseEvent| {
+            if entry.kind == EntryKind::Folder {
+                open_folder(entry.clone(), dispatch.clone());
             }
-        });
-    }
+        })
+    };
 
     html! {
-          <div class={"file"} ref={div_ref}>
-              {format!("Box {}", props.name.clone())}
+          <div class={"file relative"} {onclick}>
+              <button class={"absolute top-1 right-1"} onclick={on_star}>
+                  if props.entry.starred {
+                      <Icon icon_id={IconId::BootstrapStarFill} width={"16px"} height={"16px"}/>
+                  } else {
+                      <Icon icon_id={IconId::BootstrapStar} width={"16px"} height={"16px"}/>
+                  }
+              </button>
+              <div class={"flex flex-col items-center gap-2 break-all text-center px-2"}>
+                  if props.entry.kind == EntryKind::Folder {
+                      <Icon icon_id={IconId::BootstrapFolder}/>
+                  } else if let Some(url) = &*thumbnail {
+                      <img src={url.to_string()} alt={props.entry.name.clone()} class={"max-h-32 max-w-full rounded object-contain"}/>
+                  } else {
+                      <Icon icon_id={IconId::BootstrapFileEarmark}/>
+                  }
+                  {&props.entry.name}
+              </div>
           </div>
 
     }
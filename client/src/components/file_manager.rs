@@ -1,62 +1,131 @@
 use std::{any::Any, rc::Rc};
 
-use gloo::events::EventListener;
+use gloo::{events::EventListener, timers::callback::Timeout};
 use gloo_console::log;
 // use gloo_net::http::Request;
 use reqwasm::http::Request;
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
-use web_sys::{window, HtmlDivElement, Window};
+use web_sys::{
+    window, HtmlDivElement, IntersectionObserver, IntersectionObserverEntry, Window,
+};
 use yew::prelude::*;
+use yew_icons::{Icon, IconId};
 use yewdux::prelude::use_store;
 
 use crate::{
     components::file::*,
     pages::dashboard::SearchContext,
-    store::{set_row_size, set_total_count, Store},
-    utils::send_get_request,
+    store::{leave_folder, load_folder, load_more, search_files, set_row_size, FileEntry, Store},
 };
 
 #[function_component(FileManager)]
 pub fn file_manager() -> Html {
-    let (_, dispatch) = use_store::<Store>();
+    let (store, dispatch) = use_store::<Store>();
+    let current_folder = store.current_folder();
     let search_ctx = use_context::<SearchContext>().unwrap();
     let query = search_ctx.query.to_owned();
-    let item_count = use_state(|| 0);
-    let count_state = item_count.clone();
 
     let dispatch_clone = dispatch.clone();
     use_effect_with_deps(
-        move |_| {
+        move |(folder, category): &(Option<String>, Option<String>)| {
+            let (folder, category) = (folder.clone(), category.clone());
             wasm_bindgen_futures::spawn_local(async move {
-                let response = send_get_request("/api/count").await;
-                let count = response.unwrap().parse::<u32>().unwrap();
-                set_total_count(count, dispatch_clone);
-                count_state.set(count);
+                load_folder(folder, category, dispatch_clone).await;
             });
             || ()
         },
-        (),
+        (current_folder.clone(), store.category.clone()),
     );
-    let file_names = (0..*item_count)
-        .map(
-            |i| i.to_string(), /* match state.loaded_files.get(&i) {
-                                   Some(name) => name.to_string(),
-                                   None => String::new(),
-                               } */
-        )
-        .filter(|name| {
-            if name.len() > 0 {
-                name.contains(&query)
-            } else {
-                false
-            }
-        })
-        .collect::<Vec<String>>();
-    let files = file_names
+
+    let search_results = use_state(|| None::<Vec<FileEntry>>);
+    let search_generation = use_mut_ref(|| 0u32);
+    {
+        let search_results = search_results.clone();
+        use_effect_with_deps(
+            move |(query, category): &(String, Option<String>)| {
+                *search_generation.borrow_mut() += 1;
+                let generation = *search_generation.borrow();
+                let query = query.trim().to_owned();
+                let category = category.clone();
+
+                let timeout = if query.is_empty() {
+                    search_results.set(None);
+                    None
+                } else {
+                    Some(Timeout::new(250, move || {
+                        wasm_bindgen_futures::spawn_local(async move {
+                            let results = search_files(&query, &category).await;
+                            if *search_generation.borrow() == generation {
+                                search_results.set(Some(results.unwrap_or_default()));
+                            }
+                        });
+                    }))
+                };
+                move || drop(timeout)
+            },
+            (query.clone(), store.category.clone()),
+        );
+    }
+
+    let on_up = {
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| leave_folder(dispatch.clone()))
+    };
+    let current_path = store
+        .folder_trail
         .iter()
-        .map(|i| html! {<File name={i.clone()} />})
+        .map(|folder| folder.name.clone())
+        .collect::<Vec<String>>()
+        .join("/");
+
+    let searching = !query.trim().is_empty();
+    let entries = match &*search_results {
+        Some(results) if searching => results,
+        _ => &store.items,
+    };
+    let files = entries
+        .iter()
+        .map(|entry| html! {<File key={entry.id.clone()} entry={entry.clone()} />})
         .collect::<Vec<Html>>();
 
+    let sentinel_ref = use_node_ref();
+    {
+        let sentinel = sentinel_ref.clone();
+        let dispatch = dispatch.clone();
+        use_effect_with_deps(
+            move |_| {
+                let sentinel = sentinel.cast::<HtmlDivElement>();
+
+                let callback = Closure::wrap(Box::new(
+                    move |entries: Vec<JsValue>, _observer: IntersectionObserver| {
+                        let is_intersecting = entries.into_iter().any(|entry| {
+                            IntersectionObserverEntry::from(entry).is_intersecting()
+                        });
+                        if is_intersecting {
+                            let dispatch = dispatch.clone();
+                            wasm_bindgen_futures::spawn_local(async move {
+                                load_more(dispatch).await;
+                            });
+                        }
+                    },
+                )
+                    as Box<dyn FnMut(Vec<JsValue>, IntersectionObserver)>);
+
+                let observer =
+                    IntersectionObserver::new(callback.as_ref().unchecked_ref()).unwrap();
+                if let Some(sentinel) = &sentinel {
+                    observer.observe(sentinel);
+                }
+
+                move || {
+                    observer.disconnect();
+                    drop(callback);
+                }
+            },
+            (store.items.len(), store.next_cursor.clone()),
+        );
+    }
+
     let div_ref = use_node_ref();
     use_effect_with_deps(
         {
@@ -118,13 +187,24 @@ pub fn file_manager() -> Html {
 
     html! {
         <div class={"file-display"} ref={div_ref}>
-            if *item_count > 0 {
+            if !searching && !store.folder_trail.is_empty() {
+                <div class={"col-span-full flex items-center gap-4"}>
+                    <button onclick={on_up}>
+                        <Icon icon_id={IconId::BootstrapArrowUp}/>
+                    </button>
+                    {format!("/{}", current_path)}
+                </div>
+            }
+            if !files.is_empty() {
                 {files}
             } else {
                 <div class={"col-span-full flex justify-center items-center"}>
                     {"No files found..."}
                 </div>
             }
+            if !searching && store.next_cursor.is_some() {
+                <div class={"col-span-full h-1"} ref={sentinel_ref}></div>
+            }
         </div>
     }
 }
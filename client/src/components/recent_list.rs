@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use yew::prelude::*;
+use yew_icons::{Icon, IconId};
+use yew_router::prelude::*;
+use yewdux::prelude::use_store;
+
+use crate::{
+    store::{open_shared_folder, EntryKind, FileEntry, Store},
+    utils::send_get_request,
+    Route,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RecentItem {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub action: String,
+    pub at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentListing {
+    items: Vec<RecentItem>,
+}
+
+async fn fetch_recent() -> Vec<RecentItem> {
+    send_get_request("/api/recent")
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<RecentListing>(&response).ok())
+        .map(|listing| listing.items)
+        .unwrap_or_default()
+}
+
+#[function_component(RecentList)]
+pub fn recent_list() -> Html {
+    let items = use_state(Vec::<RecentItem>::new);
+    let (_, dispatch) = use_store::<Store>();
+    let navigator = use_navigator();
+    {
+        let items = items.clone();
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    items.set(fetch_recent().await);
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let open = |entry: FileEntry| {
+        let dispatch = dispatch.clone();
+        let navigator = navigator.clone();
+        Callback::from(move |_: MouseEvent| {
+            open_shared_folder(entry.clone(), dispatch.clone());
+            if let Some(navigator) = &navigator {
+                navigator.push(&Route::Home);
+            }
+        })
+    };
+
+    let rows = items
+        .iter()
+        .map(|item| {
+            let action = match item.action.as_str() {
+                "uploaded" => "Uploaded",
+                "opened" => "Opened",
+                _ => "Modified",
+            };
+            let when = item.at.replace('T', " ");
+            let when = when.get(..16).unwrap_or(&when);
+            html! {
+                <div key={item.entry.id.clone()} class={"flex items-center gap-4 bg-accent-1 rounded-lg shadow-md px-4 py-2"}>
+                    if item.entry.kind == EntryKind::Folder {
+                        <Icon icon_id={IconId::BootstrapFolder}/>
+                    } else {
+                        <Icon icon_id={IconId::BootstrapFileEarmark}/>
+                    }
+                    <div class={"flex flex-col flex-grow break-all"}>
+                        {&item.entry.name}
+                        <span class={"text-sm"}>{format!("{} {}", action, when)}</span>
+                    </div>
+                    if item.entry.kind == EntryKind::Folder {
+                        <button onclick={open(item.entry.clone())}>{"Open"}</button>
+                    } else {
+                        <a href={format!("/api/download/{}?attachment=true", item.entry.id)}>
+                            {"Download"}
+                        </a>
+                    }
+                </div>
+            }
+        })
+        .collect::<Vec<Html>>();
+
+    html! {
+        <div class={"flex flex-col gap-2 p-4 pl-14"}>
+            <span>{"Recent"}</span>
+            if rows.is_empty() {
+                <div class={"flex justify-center items-center"}>
+                    {"No recent activity"}
+                </div>
+            } else {
+                {rows}
+            }
+        </div>
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use yew::prelude::*;
+use yew_icons::{Icon, IconId};
+use yew_router::prelude::*;
+use yewdux::prelude::use_store;
+
+use crate::{
+    store::{open_shared_folder, EntryKind, FileEntry, Store},
+    utils::send_get_request,
+    Route,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SharedItem {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub access: String,
+    pub shared_by: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SharedListing {
+    items: Vec<SharedItem>,
+}
+
+async fn fetch_shared() -> Vec<SharedItem> {
+    send_get_request("/api/shared")
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<SharedListing>(&response).ok())
+        .map(|listing| listing.items)
+        .unwrap_or_default()
+}
+
+#[function_component(SharedList)]
+pub fn shared_list() -> Html {
+    let items = use_state(Vec::<SharedItem>::new);
+    let (_, dispatch) = use_store::<Store>();
+    let navigator = use_navigator();
+    {
+        let items = items.clone();
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    items.set(fetch_shared().await);
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let open = |entry: FileEntry| {
+        let dispatch = dispatch.clone();
+        let navigator = navigator.clone();
+        Callback::from(move |_: MouseEvent| {
+            open_shared_folder(entry.clone(), dispatch.clone());
+            if let Some(navigator) = &navigator {
+                navigator.push(&Route::Home);
+            }
+        })
+    };
+
+    let rows = items
+        .iter()
+        .map(|item| {
+            let access = if item.access == "write" { "can edit" } else { "can view" };
+            html! {
+                <div key={item.entry.id.clone()} class={"flex items-center gap-4 bg-accent-1 rounded-lg shadow-md px-4 py-2"}>
+                    if item.entry.kind == EntryKind::Folder {
+                        <Icon icon_id={IconId::BootstrapFolder}/>
+                    } else {
+                        <Icon icon_id={IconId::BootstrapFileEarmark}/>
+                    }
+                    <div class={"flex flex-col flex-grow break-all"}>
+                        {&item.entry.name}
+                        <span class={"text-sm"}>
+                            {format!("Shared by {} · {}", item.shared_by, access)}
+                        </span>
+                    </div>
+                    if item.entry.kind == EntryKind::Folder {
+                        <button onclick={open(item.entry.clone())}>{"Open"}</button>
+                    } else {
+                        <a href={format!("/api/download/{}?attachment=true", item.entry.id)}>
+                            {"Download"}
+                        </a>
+                    }
+                </div>
+            }
+        })
+        .collect::<Vec<Html>>();
+
+    html! {
+        <div class={"flex flex-col gap-2 p-4 pl-14"}>
+            <span>{"Shared with me"}</span>
+            if rows.is_empty() {
+                <div class={"flex justify-center items-center"}>
+                    {"Nothing has been shared with you yet"}
+                </div>
+            } else {
+                {rows}
+            }
+        </div>
+    }
+}
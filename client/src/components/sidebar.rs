@@ -1,10 +1,17 @@
 use yew::prelude::*;
 use yew_icons::IconId;
+use yew_router::prelude::*;
+use yewdux::prelude::use_store;
 
-use crate::components::sidebar_button::*;
+use crate::{
+    components::{sidebar_button::*, usage::Usage},
+    store::{set_category, Store},
+    Route,
+};
 
 #[function_component(Sidebar)]
 pub fn sidebar() -> Html {
+    let (store, dispatch) = use_store::<Store>();
     let hovering = use_state(|| false);
     let onmouseenter = {
         let hovering = hovering.clone();
@@ -14,10 +21,36 @@ pub fn sidebar() -> Html {
         let hovering = hovering.clone();
         Callback::from(move |_| hovering.set(false))
     };
+    let navigator = use_navigator();
+    let route = use_route::<Route>();
+    let on_files = route == Some(Route::Home);
+    let filter = |category: Option<&'static str>| {
+        let dispatch = dispatch.clone();
+        let navigator = navigator.clone();
+        Callback::from(move |_: MouseEvent| {
+            set_category(category.map(str::to_owned), dispatch.clone());
+            if let Some(navigator) = &navigator {
+                navigator.push(&Route::Home);
+            }
+        })
+    };
+    let open = |target: Route| {
+        let navigator = navigator.clone();
+        Callback::from(move |_: MouseEvent| {
+            if let Some(navigator) = &navigator {
+                navigator.push(&target);
+            }
+        })
+    };
     html! {
       <div class={"sidebar"} {onmouseenter} {onmouseleave}>
-          <SidebarButton button_text={"All"} hovering={*hovering} icon={IconId::BootstrapFileEarmark}/>
-          <SidebarButton button_text={"Images"} hovering={*hovering} icon={IconId::BootstrapFileEarmarkImage}/>
+          <SidebarButton button_text={"All"} hovering={*hovering} icon={IconId::BootstrapFileEarmark} active={on_files && store.category.is_none()} onclick={filter(None)}/>
+          <SidebarButton button_text={"Images"} hovering={*hovering} icon={IconId::BootstrapFileEarmarkImage} active={on_files && store.category.as_deref() == Some("image")} onclick={filter(Some("image"))}/>
+          <SidebarButton button_text={"Starred"} hovering={*hovering} icon={IconId::BootstrapStar} active={route == Some(Route::Starred)} onclick={open(Route::Starred)}/>
+          <SidebarButton button_text={"Recent"} hovering={*hovering} icon={IconId::BootstrapClockHistory} active={route == Some(Route::Recent)} onclick={open(Route::Recent)}/>
+          <SidebarButton button_text={"Shared with me"} hovering={*hovering} icon={IconId::BootstrapPeople} active={route == Some(Route::Shared)} onclick={open(Route::Shared)}/>
+          <SidebarButton button_text={"Trash"} hovering={*hovering} icon={IconId::BootstrapTrash} active={route == Some(Route::Trash)} onclick={open(Route::Trash)}/>
+          <Usage hovering={*hovering}/>
       </div>
     }
 }
@@ -6,6 +6,10 @@ pub struct Props {
     pub button_text: String,
     pub icon: IconId,
     pub hovering: bool,
+    #[prop_or_default]
+    pub active: bool,
+    #[prop_or_default]
+    pub onclick: Callback<MouseEvent>,
 }
 
 #[function_component(SidebarButton)]
@@ -23,10 +27,11 @@ pub fn sidebar_button(props: &Props) -> Html {
         <button
             {onmouseenter}
             {onmouseleave}
+            onclick={props.onclick.clone()}
             class={format!("sidebar-item {}", if props.hovering {"justify-end mr-2"} else {""})}
         >
             if props.hovering {
-                <p class={format!("mx-auto transition-all {}", if *local_hovering {"text-black"} else {""})}>
+                <p class={format!("mx-auto transition-all {}", if *local_hovering || props.active {"text-black"} else {""})}>
                     {&props.button_text}
                 </p>
             }
@@ -0,0 +1,121 @@
+use serde::Deserialize;
+use yew::prelude::*;
+use yew_icons::{Icon, IconId};
+use yew_router::prelude::*;
+use yewdux::prelude::use_store;
+
+use crate::{
+    store::{open_shared_folder, EntryKind, FileEntry, Store},
+    utils::{send_get_request, send_post_request},
+    Route,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StarredItem {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub starred_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StarredListing {
+    items: Vec<StarredItem>,
+}
+
+async fn fetch_starred() -> Vec<StarredItem> {
+    send_get_request("/api/starred")
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<StarredListing>(&response).ok())
+        .map(|listing| listing.items)
+        .unwrap_or_default()
+}
+
+#[function_component(StarredList)]
+pub fn starred_list() -> Html {
+    let items = use_state(Vec::<StarredItem>::new);
+    let (_, dispatch) = use_store::<Store>();
+    let navigator = use_navigator();
+    let reload = {
+        let items = items.clone();
+        Callback::from(move |_: ()| {
+            let items = items.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                items.set(fetch_starred().await);
+            });
+        })
+    };
+    {
+        let reload = reload.clone();
+        use_effect_with_deps(
+            move |_| {
+                reload.emit(());
+                || ()
+            },
+            (),
+        );
+    }
+
+    let open = |entry: FileEntry| {
+        let dispatch = dispatch.clone();
+        let navigator = navigator.clone();
+        Callback::from(move |_: MouseEvent| {
+            open_shared_folder(entry.clone(), dispatch.clone());
+            if let Some(navigator) = &navigator {
+                navigator.push(&Route::Home);
+            }
+        })
+    };
+    let unstar = |id: String| {
+        let reload = reload.clone();
+        Callback::from(move |_: MouseEvent| {
+            let reload = reload.clone();
+            let url = format!("/api/files/{}/unstar", id);
+            wasm_bindgen_futures::spawn_local(async move {
+                send_post_request(&url, &()).await.ok();
+                reload.emit(());
+            });
+        })
+    };
+
+    let rows = items
+        .iter()
+        .map(|item| {
+            let starred = item.starred_at.split('T').next().unwrap_or_default().to_owned();
+            html! {
+                <div key={item.entry.id.clone()} class={"flex items-center gap-4 bg-accent-1 rounded-lg shadow-md px-4 py-2"}>
+                    if item.entry.kind == EntryKind::Folder {
+                        <Icon icon_id={IconId::BootstrapFolder}/>
+                    } else {
+                        <Icon icon_id={IconId::BootstrapFileEarmark}/>
+                    }
+                    <div class={"flex flex-col flex-grow break-all"}>
+                        {&item.entry.name}
+                        <span class={"text-sm"}>{format!("Starred {}", starred)}</span>
+                    </div>
+                    if item.entry.kind == EntryKind::Folder {
+                        <button onclick={open(item.entry.clone())}>{"Open"}</button>
+                    } else {
+                        <a href={format!("/api/download/{}?attachment=true", item.entry.id)}>
+                            {"Download"}
+                        </a>
+                    }
+                    <button onclick={unstar(item.entry.id.clone())}>{"Unstar"}</button>
+                </div>
+            }
+        })
+        .collect::<Vec<Html>>();
+
+    html! {
+        <div class={"flex flex-col gap-2 p-4 pl-14"}>
+            <span>{"Starred"}</span>
+            if rows.is_empty() {
+                <div class={"flex justify-center items-center"}>
+                    {"Nothing starred yet"}
+                </div>
+            } else {
+                {rows}
+            }
+        </div>
+    }
+}
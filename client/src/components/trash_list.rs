@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use yew::prelude::*;
+use yew_icons::{Icon, IconId};
+
+use crate::{
+    store::EntryKind,
+    utils::{send_get_request, send_post_request},
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub name: String,
+    pub kind: EntryKind,
+    pub original_path: String,
+    pub trashed: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrashListing {
+    items: Vec<TrashItem>,
+}
+
+#[derive(Debug, Serialize)]
+struct TrashAction<'a> {
+    id: &'a str,
+    conflict: &'static str,
+}
+
+async fn fetch_trash() -> Vec<TrashItem> {
+    send_get_request("/api/trash")
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<TrashListing>(&response).ok())
+        .map(|listing| listing.items)
+        .unwrap_or_default()
+}
+
+#[function_component(TrashList)]
+pub fn trash_list() -> Html {
+    let items = use_state(Vec::<TrashItem>::new);
+    let reload = {
+        let items = items.clone();
+        Callback::from(move |_: ()| {
+            let items = items.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                items.set(fetch_trash().await);
+            });
+        })
+    };
+    {
+        let reload = reload.clone();
+        use_effect_with_deps(
+            move |_| {
+                reload.emit(());
+                || ()
+            },
+            (),
+        );
+    }
+
+    let action = |url: &'static str, id: String| {
+        let reload = reload.clone();
+        Callback::from(move |_: MouseEvent| {
+            let reload = reload.clone();
+            let id = id.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let body = TrashAction {
+                    id: &id,
+                    conflict: "rename",
+                };
+                send_post_request(url, &body).await.ok();
+                reload.emit(());
+            });
+        })
+    };
+    let on_empty = {
+        let reload = reload.clone();
+        Callback::from(move |_: MouseEvent| {
+            let reload = reload.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                send_post_request("/api/trash/empty", &()).await.ok();
+                reload.emit(());
+            });
+        })
+    };
+
+    let rows = items
+        .iter()
+        .map(|item| {
+            let deleted = item.trashed.split('T').next().unwrap_or_default().to_owned();
+            html! {
+                <div key={item.id.clone()} class={"flex items-center gap-4 bg-accent-1 rounded-lg shadow-md px-4 py-2"}>
+                    if item.kind == EntryKind::Folder {
+                        <Icon icon_id={IconId::BootstrapFolder}/>
+                    } else {
+                        <Icon icon_id={IconId::BootstrapFileEarmark}/>
+                    }
+                    <div class={"flex flex-col flex-grow break-all"}>
+                        {&item.name}
+                        <span class={"text-sm"}>
+                            {format!("From {} · deleted {}", item.original_path, deleted)}
+                        </span>
+                    </div>
+                    <button onclick={action("/api/trash/restore", item.id.clone())}>
+                        {"Restore"}
+                    </button>
+                    <button onclick={action("/api/trash/delete", item.id.clone())}>
+                        {"Delete forever"}
+                    </button>
+                </div>
+            }
+        })
+        .collect::<Vec<Html>>();
+
+    html! {
+        <div class={"flex flex-col gap-2 p-4 pl-14"}>
+            <div class={"flex items-center justify-between"}>
+                <span>{"Trash"}</span>
+                if !items.is_empty() {
+                    <button onclick={on_empty}>{"Empty trash"}</button>
+                }
+            </div>
+            if rows.is_empty() {
+                <div class={"flex justify-center items-center"}>
+                    {"Trash is empty"}
+                </div>
+            } else {
+                {rows}
+            }
+        </div>
+    }
+}
@@ -0,0 +1,76 @@
+use serde::Deserialize;
+use yew::prelude::*;
+
+use crate::utils::send_get_request;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UsageInfo {
+    pub used: u64,
+    pub limit: Option<u64>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct UsageProps {
+    pub hovering: bool,
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[function_component(Usage)]
+pub fn usage(props: &UsageProps) -> Html {
+    let usage = use_state(|| None::<UsageInfo>);
+    {
+        let usage = usage.clone();
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    let info = send_get_request("/api/usage")
+                        .await
+                        .ok()
+                        .and_then(|response| serde_json::from_str::<UsageInfo>(&response).ok());
+                    usage.set(info);
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let info = match &*usage {
+        Some(info) => info.clone(),
+        None => return html! {},
+    };
+    let percent = match info.limit {
+        Some(limit) if limit > 0 => (info.used as f64 / limit as f64 * 100.0).min(100.0),
+        _ => 0.0,
+    };
+
+    html! {
+        <div class={"mt-auto mx-2 flex flex-col gap-1 text-xs text-timberwolf"}>
+            <div class={"h-1 w-full rounded bg-smoky-black"}>
+                <div class={"h-1 rounded bg-cornell-red"} style={format!("width: {:.1}%", percent)}></div>
+            </div>
+            if props.hovering {
+                <p>
+                    {match info.limit {
+                        Some(limit) => format!("{} of {} used", format_size(info.used), format_size(limit)),
+                        None => format!("{} used", format_size(info.used)),
+                    }}
+                </p>
+            }
+        </div>
+    }
+}
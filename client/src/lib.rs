@@ -4,18 +4,31 @@ use yew_router::{
     prelude::*,
 };
 
-use pages::dashboard::Dashboard;
+use pages::{
+    dashboard::Dashboard, recent::Recent, share::Share, shared::Shared, starred::Starred,
+    trash::Trash,
+};
 
 mod pages {
     pub mod dashboard;
+    pub mod recent;
+    pub mod share;
+    pub mod shared;
+    pub mod starred;
+    pub mod trash;
 }
 
 mod components {
     pub mod file;
     pub mod file_manager;
     pub mod header;
+    pub mod recent_list;
     pub mod sidebar;
+    pub mod shared_list;
+    pub mod starred_list;
     pub mod sidebar_button;
+    pub mod trash_list;
+    pub mod usage;
 }
 
 mod store;
@@ -25,6 +38,16 @@ mod utils;
 enum Route {
     #[at("/")]
     Home,
+    #[at("/trash")]
+    Trash,
+    #[at("/shared")]
+    Shared,
+    #[at("/starred")]
+    Starred,
+    #[at("/recent")]
+    Recent,
+    #[at("/s/:token")]
+    Share { token: String },
 }
 
 #[derive(Properties, PartialEq, Debug)]
@@ -35,6 +58,11 @@ pub struct ServerAppProps {
 fn switch(routes: Route) -> Html {
     match routes {
         Route::Home => html! {<Dashboard />},
+        Route::Trash => html! {<Trash />},
+        Route::Shared => html! {<Shared />},
+        Route::Starred => html! {<Starred />},
+        Route::Recent => html! {<Recent />},
+        Route::Share { token } => html! {<Share {token} />},
     }
 }
 
@@ -0,0 +1,25 @@
+use yew::prelude::*;
+
+use crate::{
+    components::{header::Header, recent_list::RecentList, sidebar::Sidebar},
+    pages::dashboard::{Search, SearchContext},
+};
+
+#[function_component(Recent)]
+pub fn recent() -> Html {
+    let search = use_reducer(|| Search {
+        query: "".to_owned()
+    });
+
+    html! {
+        <div class={"flex h-screen flex-col"}>
+            <ContextProvider<SearchContext> context = {search}>
+                <Header />
+                <div class={"overflow-auto flex-grow relative"}>
+                    <Sidebar />
+                    <RecentList />
+                </div>
+            </ContextProvider<SearchContext>>
+        </div>
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::{EventTarget, HtmlInputElement};
+use yew::prelude::*;
+
+use crate::{
+    components::usage::format_size,
+    utils::{send_get_request, send_post_request},
+};
+
+#[derive(Properties, PartialEq)]
+pub struct ShareProps {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PublicShare {
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+    pub expires: Option<String>,
+    pub protected: bool,
+    pub downloads_remaining: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ShareState {
+    Loading,
+    Ready(PublicShare),
+    Unavailable,
+}
+
+#[derive(Debug, Serialize)]
+struct UnlockShare<'a> {
+    password: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShareTicket {
+    ticket: String,
+}
+
+#[function_component(Share)]
+pub fn share(props: &ShareProps) -> Html {
+    let state = use_state(|| ShareState::Loading);
+    let password = use_state(String::new);
+    let ticket = use_state(|| None::<String>);
+    let error = use_state(|| None::<&'static str>);
+
+    {
+        let state = state.clone();
+        use_effect_with_deps(
+            move |token: &String| {
+                let url = format!("/share/{}", token);
+                wasm_bindgen_futures::spawn_local(async move {
+                    let share = send_get_request(&url)
+                        .await
+                        .ok()
+                        .and_then(|response| serde_json::from_str::<PublicShare>(&response).ok());
+                    state.set(match share {
+                        Some(share) => ShareState::Ready(share),
+                        None => ShareState::Unavailable,
+                    });
+                });
+                || ()
+            },
+            props.token.clone(),
+        );
+    }
+
+    let oninput = {
+        let password = password.clone();
+        Callback::from(move |e: InputEvent| {
+            let target: Option<EventTarget> = e.target();
+            if let Some(input) = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok()) {
+                password.set(input.value());
+            }
+        })
+    };
+
+    let on_unlock = {
+        let token = props.token.clone();
+        let password = password.clone();
+        let ticket = ticket.clone();
+        let error = error.clone();
+        Callback::from(move |_: MouseEvent| {
+            let url = format!("/share/{}/unlock", token);
+            let password = (*password).clone();
+            let ticket = ticket.clone();
+            let error = error.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let unlocked = send_post_request(&url, &UnlockShare { password: &password })
+                    .await
+                    .ok()
+                    .and_then(|response| serde_json::from_str::<ShareTicket>(&response).ok());
+                match unlocked {
+                    Some(unlocked) => {
+                        error.set(None);
+                        ticket.set(Some(unlocked.ticket));
+                    }
+                    None => error.set(Some("Wrong password")),
+                }
+            });
+        })
+    };
+
+    let content = match &*state {
+        ShareState::Loading => html! {<span>{"Loading..."}</span>},
+        ShareState::Unavailable => html! {
+            <span>{"This link doesn't exist or is no longer available."}</span>
+        },
+        ShareState::Ready(share) => {
+            let mut href = format!("/share/{}/download?attachment=true", props.token);
+            if let Some(ticket) = &*ticket {
+                href.push_str(&format!("&ticket={}", ticket));
+            }
+            let locked = share.protected && ticket.is_none();
+            html! {
+                <>
+                    <span class={"font-bold break-all"}>{&share.name}</span>
+                    <span class={"text-sm"}>{format!("{} · {}", format_size(share.size), share.mime)}</span>
+                    if let Some(expires) = &share.expires {
+                        <span class={"text-sm"}>
+                            {format!("Expires {}", expires.split('T').next().unwrap_or_default())}
+                        </span>
+                    }
+                    if let Some(remaining) = share.downloads_remaining {
+                        <span class={"text-sm"}>{format!("{} downloads left", remaining)}</span>
+                    }
+                    if locked {
+                        <input type="password" class="bg-transparent border-b-black border-b-2"
+                            placeholder="Password" {oninput}/>
+                        <button onclick={on_unlock}>{"Unlock"}</button>
+                        if let Some(error) = *error {
+                            <span class={"text-sm text-cornell-red"}>{error}</span>
+                        }
+                    } else {
+                        <a href={href}>{"Download"}</a>
+                    }
+                </>
+            }
+        }
+    };
+
+    html! {
+        <div class={"flex h-screen flex-col"}>
+            <div class={"header"}>
+                <div class={"text-cornell-red text-bold font-bold"}>
+                    {"FiZap"}
+                </div>
+            </div>
+            <div class={"flex flex-col items-center gap-2 p-8"}>
+                <div class={"flex flex-col gap-2 bg-accent-1 rounded-lg shadow-md px-6 py-4"}>
+                    {content}
+                </div>
+            </div>
+        </div>
+    }
+}
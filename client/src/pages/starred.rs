@@ -0,0 +1,25 @@
+use yew::prelude::*;
+
+use crate::{
+    components::{header::Header, sidebar::Sidebar, starred_list::StarredList},
+    pages::dashboard::{Search, SearchContext},
+};
+
+#[function_component(Starred)]
+pub fn starred() -> Html {
+    let search = use_reducer(|| Search {
+        query: "".to_owned()
+    });
+
+    html! {
+        <div class={"flex h-screen flex-col"}>
+            <ContextProvider<SearchContext> context = {search}>
+                <Header />
+                <div class={"overflow-auto flex-grow relative"}>
+                    <Sidebar />
+                    <StarredList />
+                </div>
+            </ContextProvider<SearchContext>>
+        </div>
+    }
+}
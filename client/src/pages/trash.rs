@@ -0,0 +1,25 @@
+use yew::prelude::*;
+
+use crate::{
+    components::{header::Header, sidebar::Sidebar, trash_list::TrashList},
+    pages::dashboard::{Search, SearchContext},
+};
+
+#[function_component(Trash)]
+pub fn trash() -> Html {
+    let search = use_reducer(|| Search {
+        query: "".to_owned()
+    });
+
+    html! {
+        <div class={"flex h-screen flex-col"}>
+            <ContextProvider<SearchContext> context = {search}>
+                <Header />
+                <div class={"overflow-auto flex-grow relative"}>
+                    <Sidebar />
+                    <TrashList />
+                </div>
+            </ContextProvider<SearchContext>>
+        </div>
+    }
+}
@@ -1,94 +1,201 @@
-use std::{collections::HashMap, rc::Rc};
-
-use gloo_console::log;
-use gloo_utils::format::JsValueSerdeExt;
 use serde::{Deserialize, Serialize};
-use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::spawn_local;
 use yewdux::prelude::*;
 
-use crate::utils::send_post_request;
+use crate::utils::{send_get_request, send_post_request};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Folder,
+    File,
+}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
-pub struct ItemData {
-    pub index: u32,
+pub struct FileEntry {
+    pub id: String,
     pub name: String,
+    pub kind: EntryKind,
+    #[serde(default)]
+    pub mime: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub starred: bool,
 }
 
 #[derive(Debug, Default, PartialEq, Store, Serialize, Deserialize, Clone)]
 #[store(storage = "local", storage_tab_sync)]
 pub struct Store {
-    pub loaded_items: Vec<ItemData>,
+    #[serde(default)]
+    pub items: Vec<FileEntry>,
     pub row_size: u32,
-    pub total_items: u32,
+    #[serde(default)]
+    pub folder_trail: Vec<FileEntry>,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    #[serde(default)]
+    pub total: u64,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FilePage {
+    items: Vec<FileEntry>,
+    next_cursor: Option<String>,
+    total: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchPage {
+    items: Vec<FileEntry>,
+}
+
+impl Store {
+    pub fn current_folder(&self) -> Option<String> {
+        self.folder_trail.last().map(|folder| folder.id.clone())
+    }
+
+    pub fn page_size(&self) -> u32 {
+        (self.row_size * 8).max(40)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GetFiles {
-    indices: Vec<u32>,
-    count: u32,
+async fn fetch_page(
+    folder: &Option<String>,
+    category: &Option<String>,
+    cursor: &Option<String>,
+    limit: u32,
+) -> Option<FilePage> {
+    let mut url = format!("/api/files?limit={}", limit);
+    if let Some(id) = folder {
+        url.push_str(&format!("&folder={}", id));
+    }
+    if let Some(category) = category {
+        url.push_str(&format!("&type={}", category));
+    }
+    if let Some(cursor) = cursor {
+        url.push_str(&format!("&cursor={}", urlencoding::encode(cursor)));
+    }
+    send_get_request(&url)
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<FilePage>(&response).ok())
 }
 
-#[allow(unused_must_use)]
-pub async fn load_item(id: u32, dispatch: Dispatch<Store>) -> Result<(String, String), ()> {
-    let mut temp_store = Rc::new(Store::default());
-    dispatch.reduce_mut(|store| {
-        if store.loaded_items.iter().position(|x| x.index == id) == None {
-            log!(format!("Id: {}", id));
-            store.loaded_items.push(ItemData {
-                index: id,
-                name: String::new(),
-            });
+pub async fn search_files(query: &str, category: &Option<String>) -> Option<Vec<FileEntry>> {
+    let mut url = format!("/api/search?limit=200&q={}", urlencoding::encode(query));
+    if let Some(category) = category {
+        url.push_str(&format!("&type={}", category));
+    }
+    send_get_request(&url)
+        .await
+        .ok()
+        .and_then(|response| serde_json::from_str::<SearchPage>(&response).ok())
+        .map(|page| page.items)
+}
+
+pub async fn load_folder(
+    folder: Option<String>,
+    category: Option<String>,
+    dispatch: Dispatch<Store>,
+) {
+    let limit = dispatch.get().page_size();
+    let page = fetch_page(&folder, &category, &None, limit).await;
+
+    dispatch.reduce_mut(move |store| {
+        if store.current_folder() == folder && store.category == category {
+            match page {
+                Some(page) => {
+                    store.items = page.items;
+                    store.next_cursor = page.next_cursor;
+                    store.total = page.total;
+                }
+                None => {
+                    store.items.clear();
+                    store.next_cursor = None;
+                    store.total = 0;
+                }
+            }
         }
-        temp_store = Rc::new(store.clone());
     });
-    let temp_store = temp_store.clone();
-
-    let count = temp_store.loaded_items.len() as u32;
-    log!(format!("total items: {:?}", temp_store.loaded_items));
-    if count % temp_store.row_size == 0 || count < temp_store.row_size {
-        let mut requested_files: Vec<ItemData> = temp_store
-            .loaded_items
-            .iter()
-            .map(|item| item.clone())
-            .filter(|item| item.name.len() == 0)
-            .collect();
-        requested_files.reverse();
-        let mut returned_files: Vec<u32> = requested_files
-            [..temp_store.row_size.min(count) as usize]
-            .iter()
-            .map(|item| item.index)
-            .collect();
-        returned_files.reverse();
-
-        let request_body = GetFiles {
-            indices: returned_files,
-            count: temp_store.total_items,
-        };
-
-        let res = serde_json::from_str::<Vec<(String, String)>>(
-            &send_post_request("/api/indices", &request_body)
-                .await
-                .unwrap(),
-        )
-        .unwrap();
-
-        let mut sorted_indices = request_body.indices;
-        sorted_indices.sort();
-        let index = sorted_indices.iter().position(|&i| i == id).unwrap();
-        let item_data = res[index].clone();
-
-        return Ok(item_data);
-    }
-    Err(())
 }
 
-pub fn set_total_count(count: u32, dispatch: Dispatch<Store>) {
+pub async fn load_more(dispatch: Dispatch<Store>) {
+    let state = dispatch.get();
+    let folder = state.current_folder();
+    let category = state.category.clone();
+    let cursor = match &state.next_cursor {
+        Some(cursor) => Some(cursor.clone()),
+        None => return,
+    };
+    let page = match fetch_page(&folder, &category, &cursor, state.page_size()).await {
+        Some(page) => page,
+        None => return,
+    };
+
     dispatch.reduce_mut(move |store| {
-        store.total_items = count;
+        if store.current_folder() == folder
+            && store.category == category
+            && store.next_cursor == cursor
+        {
+            store.items.extend(page.items);
+            store.next_cursor = page.next_cursor;
+            store.total = page.total;
+        }
+    });
+}
+
+pub fn open_folder(folder: FileEntry, dispatch: Dispatch<Store>) {
+    dispatch.reduce_mut(move |store| {
+        store.folder_trail.push(folder);
+        store.items.clear();
+        store.next_cursor = None;
     });
 }
 
+/// Opens a folder from outside the current trail, like one someone else
+/// shared, starting the trail from it.
+pub fn open_shared_folder(folder: FileEntry, dispatch: Dispatch<Store>) {
+    dispatch.reduce_mut(move |store| {
+        store.folder_trail = vec![folder];
+        store.category = None;
+        store.items.clear();
+        store.next_cursor = None;
+    });
+}
+
+pub fn leave_folder(dispatch: Dispatch<Store>) {
+    dispatch.reduce_mut(move |store| {
+        store.folder_trail.pop();
+        store.items.clear();
+        store.next_cursor = None;
+    });
+}
+
+pub fn set_category(category: Option<String>, dispatch: Dispatch<Store>) {
+    dispatch.reduce_mut(move |store| {
+        if store.category != category {
+            store.category = category;
+            store.items.clear();
+            store.next_cursor = None;
+        }
+    });
+}
+
+/// Stars or unstars an entry, updating it in the current listing.
+pub async fn set_starred(id: String, starred: bool, dispatch: Dispatch<Store>) {
+    let action = if starred { "star" } else { "unstar" };
+    let url = format!("/api/files/{}/{}", id, action);
+    if send_post_request(&url, &()).await.is_ok() {
+        dispatch.reduce_mut(move |store| {
+            if let Some(entry) = store.items.iter_mut().find(|entry| entry.id == id) {
+                entry.starred = starred;
+            }
+        });
+    }
+}
+
 pub fn set_row_size(size: u32, dispatch: Dispatch<Store>) {
     dispatch.reduce_mut(move |store| {
         store.row_size = size;
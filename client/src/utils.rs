@@ -44,3 +44,22 @@ pub async fn send_get_request(url: &str) -> Result<String, String> {
         Err(_) => Err("Failed to parse response".to_string()),
     }
 }
+
+pub async fn send_get_bytes(url: &str) -> Result<Vec<u8>, String> {
+    let token = "";
+    let auth_header = format!("Bearer {}", token);
+    let request = Request::get(url)
+        .header("Authorization", &auth_header)
+        .send()
+        .await;
+    let response = match request {
+        Ok(res) if res.ok() => res,
+        Ok(res) => return Err(format!("Request failed with status {}", res.status())),
+        Err(e) => return Err(format!("Failed to make request, {}", e)),
+    };
+
+    match response.binary().await {
+        Ok(data) => Ok(data),
+        Err(_) => Err("Failed to read response".to_string()),
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::{FindOptions, IndexOptions, UpdateOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grants::{authorize, Access},
+    metadata::FileRecord,
+    utils::{AppState, CustomError},
+};
+
+/// Entries drop out of the recent list once nobody has touched them for this long.
+const RECENT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Uploaded,
+    Opened,
+    Modified,
+}
+
+/// The last thing a user did with an entry. There is one record per user and
+/// entry, so the recent list shows each entry once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: String,
+    pub entry: ObjectId,
+    pub action: Action,
+    pub at: BsonDateTime,
+}
+
+pub async fn create_indexes(activity: &Collection<ActivityRecord>) -> mongodb::error::Result<()> {
+    activity
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"user": 1, "entry": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    activity
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"user": 1, "at": -1})
+                .build(),
+            None,
+        )
+        .await?;
+    activity
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"at": 1})
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(RECENT_RETENTION)
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Notes that `user` just acted on `record`. The recent list is a convenience,
+/// so failing to update it doesn't fail the request.
+pub async fn record_activity(data: &AppState, user: &str, record: &FileRecord, action: Action) {
+    let action = match mongodb::bson::to_bson(&action) {
+        Ok(action) => action,
+        Err(_) => return,
+    };
+    let result = data
+        .activity_collection
+        .update_one(
+            doc! {"user": user, "entry": record.id},
+            doc! {
+                "$set": {"action": action, "at": BsonDateTime::now()},
+                "$setOnInsert": {"_id": ObjectId::new()},
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await;
+    if let Err(e) = result {
+        tracing::warn!("Failed to record activity on {}: {}", record.id, e);
+    }
+}
+
+/// The entries `user` acted on most recently and can still see.
+pub async fn recent_entries(
+    data: &AppState,
+    user: &str,
+    limit: u32,
+) -> Result<Vec<(ActivityRecord, FileRecord)>, CustomError> {
+    let options = FindOptions::builder()
+        .sort(doc! {"at": -1, "_id": -1})
+        .build();
+    let mut activity = data
+        .activity_collection
+        .find(doc! {"user": user}, options)
+        .await?;
+    let mut recent = vec![];
+    while let Some(event) = activity.try_next().await? {
+        if recent.len() >= limit as usize {
+            break;
+        }
+        match authorize(data, user, &event.entry.to_hex(), Access::Read).await {
+            Ok(record) => recent.push((event, record)),
+            Err(CustomError::MissingPath) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(recent)
+}
+
+pub async fn remove_activity(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    data.activity_collection
+        .delete_many(doc! {"entry": record.id}, None)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,621 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use actix_web::web;
+use flate2::read::GzDecoder;
+use futures_util::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::{
+    grants::Destination,
+    metadata::{find_child, store_content, Content, EntryKind, FileRecord, Target},
+    quota::remaining_quota,
+    staging::{StagedFile, StagingKey, SyncStagedFile},
+    utils::{validate_file_name, AppState, ConflictPolicy, CustomError},
+};
+
+const SYMLINK_MODE: u32 = 0o120000;
+const FILE_TYPE_MASK: u32 = 0o170000;
+/// How much bigger a tar stream can be than the files in it, per entry: a
+/// header block, plus padding out to the next block.
+const TAR_ENTRY_OVERHEAD: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn detect(record: &FileRecord) -> Option<Self> {
+        if record.kind != EntryKind::File {
+            return None;
+        }
+        let name = record.name.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            return Some(ArchiveFormat::TarGz);
+        }
+        match record.mime.as_str() {
+            "application/zip" => Some(ArchiveFormat::Zip),
+            "application/x-tar" => Some(ArchiveFormat::Tar),
+            "application/gzip" | "application/x-gzip" => Some(ArchiveFormat::TarGz),
+            _ if name.ends_with(".zip") => Some(ArchiveFormat::Zip),
+            _ if name.ends_with(".tar") => Some(ArchiveFormat::Tar),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveItem {
+    path: String,
+    kind: EntryKind,
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveListing {
+    items: Vec<ArchiveItem>,
+    size: u64,
+    truncated: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ExtractSummary {
+    folders: u64,
+    files: u64,
+    size: u64,
+}
+
+/// An entry written out to the staging directory, waiting to be stored.
+struct Extracted {
+    components: Vec<String>,
+    kind: EntryKind,
+    staged: Option<PathBuf>,
+}
+
+struct Limits {
+    entries: usize,
+    size: u64,
+    /// Whether `size` comes from the owner's remaining quota rather than the
+    /// extraction limit, which decides the error reported when it runs out.
+    quota_bound: bool,
+}
+
+impl Limits {
+    fn exceeded(&self) -> CustomError {
+        if self.quota_bound {
+            CustomError::QuotaExceeded
+        } else {
+            CustomError::ArchiveTooLarge
+        }
+    }
+}
+
+/// Stops reading once more than `remaining` bytes have come through, so a
+/// small compressed archive can't make us decompress without end.
+struct CappedReader<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R> CappedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        CappedReader {
+            inner,
+            remaining: limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl<R: Read> Read for CappedReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let wanted = usize::try_from(self.remaining.saturating_add(1))
+            .map_or(buffer.len(), |wanted| wanted.min(buffer.len()));
+        let read = self.inner.read(&mut buffer[..wanted])?;
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("archive is larger than allowed"));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+fn invalid_archive<E>(_: E) -> CustomError {
+    CustomError::InvalidArchive
+}
+
+/// Splits an entry name into components that are safe to create, refusing any
+/// that would climb out of the extraction folder.
+fn sanitize_path(raw: &str) -> Result<Vec<String>, CustomError> {
+    let mut components = vec![];
+    for component in raw.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(CustomError::InvalidArchive),
+            component => components.push(
+                validate_file_name(component)
+                    .map_err(invalid_archive)?
+                    .to_owned(),
+            ),
+        }
+    }
+    Ok(components)
+}
+
+/// Calls `visit` with each file and folder in the archive, in order, until it
+/// returns false. Links and other special entries are skipped. Compressed
+/// tarballs are only decompressed as far as `limits` allows.
+fn walk_archive(
+    key: Option<&StagingKey>,
+    path: &Path,
+    format: ArchiveFormat,
+    limits: &Limits,
+    mut visit: impl FnMut(&str, EntryKind, u64, &mut dyn Read) -> Result<bool, CustomError>,
+) -> Result<(), CustomError> {
+    let file = BufReader::new(SyncStagedFile::open(key, path)?);
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(invalid_archive)?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index).map_err(invalid_archive)?;
+                if entry
+                    .unix_mode()
+                    .is_some_and(|mode| mode & FILE_TYPE_MASK == SYMLINK_MODE)
+                {
+                    continue;
+                }
+                let kind = if entry.is_dir() {
+                    EntryKind::Folder
+                } else {
+                    EntryKind::File
+                };
+                let name = entry.name().to_owned();
+                let size = entry.size();
+                if !visit(&name, kind, size, &mut entry)? {
+                    break;
+                }
+            }
+        }
+        ArchiveFormat::Tar => walk_tar(&mut tar::Archive::new(file), visit)?,
+        ArchiveFormat::TarGz => {
+            let limit = limits
+                .size
+                .saturating_add(limits.entries as u64 * TAR_ENTRY_OVERHEAD);
+            let mut archive = tar::Archive::new(CappedReader::new(GzDecoder::new(file), limit));
+            let result = walk_tar(&mut archive, visit);
+            if archive.into_inner().exceeded {
+                return Err(limits.exceeded());
+            }
+            result?
+        }
+    }
+    Ok(())
+}
+
+fn walk_tar<R: Read>(
+    archive: &mut tar::Archive<R>,
+    mut visit: impl FnMut(&str, EntryKind, u64, &mut dyn Read) -> Result<bool, CustomError>,
+) -> Result<(), CustomError> {
+    for entry in archive.entries().map_err(invalid_archive)? {
+        let mut entry = entry.map_err(invalid_archive)?;
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            tar::EntryType::Directory => EntryKind::Folder,
+            _ => continue,
+        };
+        let name = entry
+            .path()
+            .map_err(invalid_archive)?
+            .to_string_lossy()
+            .into_owned();
+        let size = entry.size();
+        if !visit(&name, kind, size, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn list_entries(
+    key: Option<&StagingKey>,
+    path: &Path,
+    format: ArchiveFormat,
+    limits: &Limits,
+) -> Result<ArchiveListing, CustomError> {
+    let mut listing = ArchiveListing {
+        items: vec![],
+        size: 0,
+        truncated: false,
+    };
+    walk_archive(key, path, format, limits, |name, kind, size, _| {
+        if listing.items.len() >= limits.entries {
+            listing.truncated = true;
+            return Ok(false);
+        }
+        let components = sanitize_path(name)?;
+        if components.is_empty() {
+            return Ok(true);
+        }
+        listing.size += size;
+        listing.items.push(ArchiveItem {
+            path: components.join("/"),
+            kind,
+            size,
+        });
+        Ok(true)
+    })?;
+    Ok(listing)
+}
+
+/// Writes every entry to `staging`. Sizes are counted from the decompressed
+/// data itself, since the sizes an archive declares can't be trusted.
+fn unpack_entries(
+    key: Option<&StagingKey>,
+    path: &Path,
+    format: ArchiveFormat,
+    staging: &Path,
+    limits: &Limits,
+) -> Result<Vec<Extracted>, CustomError> {
+    let mut extracted = vec![];
+    let mut total: u64 = 0;
+    walk_archive(key, path, format, limits, |name, kind, size, reader| {
+        if extracted.len() >= limits.entries {
+            return Err(CustomError::ArchiveTooLarge);
+        }
+        let components = sanitize_path(name)?;
+        if components.is_empty() {
+            return Ok(true);
+        }
+        let staged = match kind {
+            EntryKind::Folder => None,
+            EntryKind::File => {
+                let budget = limits.size - total;
+                if size > budget {
+                    return Err(limits.exceeded());
+                }
+                let staged = staging.join(uuid::Uuid::new_v4().to_string());
+                let mut file = SyncStagedFile::create(key, &staged)?;
+                let written =
+                    io::copy(&mut reader.take(budget + 1), &mut file).map_err(invalid_archive)?;
+                if written > budget {
+                    return Err(limits.exceeded());
+                }
+                total += written;
+                Some(staged)
+            }
+        };
+        extracted.push(Extracted {
+            components,
+            kind,
+            staged,
+        });
+        Ok(true)
+    })?;
+    Ok(extracted)
+}
+
+async fn fetch_archive(
+    data: &AppState,
+    record: &FileRecord,
+    path: &Path,
+) -> Result<(), CustomError> {
+    if record.size == 0 {
+        return Err(CustomError::InvalidArchive);
+    }
+    let mut content = data
+        .storage
+        .read(&record.content_key(), 0, record.size as u64)
+        .await?;
+    let mut file = StagedFile::create(data.staging_key.as_ref(), path).await?;
+    while let Some(chunk) = content.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Runs `work` on a local copy of the archive in its own staging folder,
+/// cleaning up everything written there afterwards.
+async fn with_local_copy<T: Send + 'static>(
+    data: &AppState,
+    user: &str,
+    record: &FileRecord,
+    work: impl FnOnce(Option<&StagingKey>, &Path, &Path) -> Result<T, CustomError> + Send + 'static,
+) -> Result<(T, PathBuf), CustomError> {
+    let staging = data
+        .opt
+        .staging_dir(user)
+        .join(format!("archive-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&staging).await?;
+    let source = staging.join("source");
+    let result = match fetch_archive(data, record, &source).await {
+        Ok(()) => {
+            let staging = staging.clone();
+            let key = data.staging_key.clone();
+            web::block(move || work(key.as_ref(), &source, &staging))
+                .await
+                .map_err(|_| CustomError::InternalError)
+                .and_then(|result| result)
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(value) => Ok((value, staging)),
+        Err(e) => {
+            fs::remove_dir_all(&staging).await.ok();
+            Err(e)
+        }
+    }
+}
+
+pub async fn list_archive(
+    data: &AppState,
+    user: &str,
+    record: &FileRecord,
+) -> Result<ArchiveListing, CustomError> {
+    let format = ArchiveFormat::detect(record).ok_or(CustomError::InvalidArchive)?;
+    let limits = Limits {
+        entries: data.opt.max_extract_entries,
+        size: data.opt.max_extract_size,
+        quota_bound: false,
+    };
+    let (listing, staging) = with_local_copy(data, user, record, move |key, source, _| {
+        list_entries(key, source, format, &limits)
+    })
+    .await?;
+    fs::remove_dir_all(&staging).await.ok();
+    Ok(listing)
+}
+
+async fn ensure_folder(
+    data: &AppState,
+    owner: &str,
+    parent: Option<ObjectId>,
+    name: &str,
+    summary: &mut ExtractSummary,
+) -> Result<ObjectId, CustomError> {
+    let files = &data.file_collection;
+    if let Some(existing) = find_child(files, owner, parent, name).await? {
+        if existing.kind == EntryKind::Folder {
+            return Ok(existing.id);
+        }
+    }
+    let name = match ConflictPolicy::Rename
+        .resolve(files, owner, parent, name)
+        .await?
+    {
+        Target::Create(name) => name,
+        Target::Replace(_) => return Err(CustomError::Conflict),
+    };
+    let record = FileRecord::new(owner, parent, &name, EntryKind::Folder);
+    files.insert_one(&record, None).await?;
+    summary.folders += 1;
+    Ok(record.id)
+}
+
+async fn store_entries(
+    data: &AppState,
+    destination: &Destination,
+    entries: Vec<Extracted>,
+    conflict: ConflictPolicy,
+) -> Result<ExtractSummary, CustomError> {
+    let owner = &destination.owner;
+    let mut summary = ExtractSummary::default();
+    let mut folders: HashMap<Vec<String>, Option<ObjectId>> = HashMap::new();
+    folders.insert(vec![], destination.folder);
+
+    for entry in entries {
+        let depth = match entry.kind {
+            EntryKind::Folder => entry.components.len(),
+            EntryKind::File => entry.components.len() - 1,
+        };
+        let mut parent = destination.folder;
+        for end in 1..=depth {
+            let prefix = &entry.components[..end];
+            parent = match folders.get(prefix) {
+                Some(folder) => *folder,
+                None => {
+                    let folder =
+                        ensure_folder(data, owner, parent, &prefix[end - 1], &mut summary).await?;
+                    folders.insert(prefix.to_vec(), Some(folder));
+                    Some(folder)
+                }
+            };
+        }
+        if let (Some(staged), Some(name)) = (&entry.staged, entry.components.last()) {
+            let record =
+                store_content(data, owner, parent, name, conflict, Content::Staged(staged)).await?;
+            summary.files += 1;
+            summary.size += record.size as u64;
+        }
+    }
+    Ok(summary)
+}
+
+pub async fn extract_archive(
+    data: &AppState,
+    user: &str,
+    record: &FileRecord,
+    destination: &Destination,
+    conflict: ConflictPolicy,
+) -> Result<ExtractSummary, CustomError> {
+    let format = ArchiveFormat::detect(record).ok_or(CustomError::InvalidArchive)?;
+    let quota = remaining_quota(data, &destination.owner).await?;
+    let limits = Limits {
+        entries: data.opt.max_extract_entries,
+        size: quota.map_or(data.opt.max_extract_size, |quota| {
+            quota.min(data.opt.max_extract_size)
+        }),
+        quota_bound: quota.is_some_and(|quota| quota < data.opt.max_extract_size),
+    };
+    let (entries, staging) = with_local_copy(data, user, record, move |key, source, staging| {
+        unpack_entries(key, source, format, staging, &limits)
+    })
+    .await?;
+    let result = store_entries(data, destination, entries, conflict).await;
+    fs::remove_dir_all(&staging).await.ok();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let dir = env::temp_dir().join(format!("fizap-archive-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        io::Write::write_all(&mut encoder, content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn limits(entries: usize, size: u64) -> Limits {
+        Limits {
+            entries,
+            size,
+            quota_bound: false,
+        }
+    }
+
+    fn unpack(
+        scratch: &Scratch,
+        archive: &[u8],
+        format: ArchiveFormat,
+        limits: &Limits,
+    ) -> Result<Vec<Extracted>, CustomError> {
+        let source = scratch.0.join("source");
+        std::fs::write(&source, archive).unwrap();
+        unpack_entries(None, &source, format, &scratch.0, limits)
+    }
+
+    #[test]
+    fn sanitizes_entry_paths() {
+        assert_eq!(sanitize_path("a/b/c.txt").unwrap(), ["a", "b", "c.txt"]);
+        assert_eq!(sanitize_path("./a//b/").unwrap(), ["a", "b"]);
+        assert_eq!(sanitize_path("a\\b").unwrap(), ["a", "b"]);
+        assert_eq!(sanitize_path("/etc/passwd").unwrap(), ["etc", "passwd"]);
+        assert!(sanitize_path("").unwrap().is_empty());
+        for raw in [
+            "../evil",
+            "a/../../evil",
+            "a\\..\\evil",
+            "a/ /b",
+            "bad\u{0}name",
+        ] {
+            assert!(
+                matches!(sanitize_path(raw), Err(CustomError::InvalidArchive)),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn unpacks_within_the_limits() {
+        let scratch = Scratch::new();
+        let archive = tarball(&[("a.txt", b"hello!"), ("dir/b.txt", b"world!")]);
+        let extracted = unpack(&scratch, &archive, ArchiveFormat::Tar, &limits(10, 12)).unwrap();
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted[1].components, ["dir", "b.txt"]);
+        let staged = extracted[1].staged.as_ref().unwrap();
+        assert_eq!(std::fs::read(staged).unwrap(), b"world!");
+    }
+
+    #[test]
+    fn stops_at_the_size_budget() {
+        let scratch = Scratch::new();
+        let archive = tarball(&[("a.txt", b"hello!"), ("b.txt", b"world!")]);
+        let result = unpack(&scratch, &archive, ArchiveFormat::Tar, &limits(10, 11));
+        assert!(matches!(result, Err(CustomError::ArchiveTooLarge)));
+
+        let quota = Limits {
+            quota_bound: true,
+            ..limits(10, 11)
+        };
+        let result = unpack(&scratch, &archive, ArchiveFormat::Tar, &quota);
+        assert!(matches!(result, Err(CustomError::QuotaExceeded)));
+    }
+
+    #[test]
+    fn stops_at_the_entry_budget() {
+        let scratch = Scratch::new();
+        let archive = tarball(&[("a", b"1"), ("b", b"2"), ("c", b"3")]);
+        let result = unpack(&scratch, &archive, ArchiveFormat::Tar, &limits(2, 100));
+        assert!(matches!(result, Err(CustomError::ArchiveTooLarge)));
+
+        let source = scratch.0.join("listed");
+        std::fs::write(&source, &archive).unwrap();
+        let listing = list_entries(None, &source, ArchiveFormat::Tar, &limits(2, 100)).unwrap();
+        assert_eq!(listing.items.len(), 2);
+        assert!(listing.truncated);
+    }
+
+    #[test]
+    fn stops_decompressing_past_the_limit() {
+        let scratch = Scratch::new();
+        let zeros = vec![0; 1024 * 1024];
+        let archive = gzip(&tarball(&[("zeros", &zeros)]));
+        assert!(archive.len() < 10 * 1024);
+
+        let source = scratch.0.join("listed");
+        std::fs::write(&source, &archive).unwrap();
+        let result = list_entries(None, &source, ArchiveFormat::TarGz, &limits(1, 64 * 1024));
+        assert!(matches!(result, Err(CustomError::ArchiveTooLarge)));
+        let result = unpack(
+            &scratch,
+            &archive,
+            ArchiveFormat::TarGz,
+            &limits(1, 64 * 1024),
+        );
+        assert!(matches!(result, Err(CustomError::ArchiveTooLarge)));
+
+        let listing = list_entries(
+            None,
+            &source,
+            ArchiveFormat::TarGz,
+            &limits(1, 2 * 1024 * 1024),
+        )
+        .unwrap();
+        assert_eq!(listing.size, 1024 * 1024);
+    }
+}
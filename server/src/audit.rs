@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use actix_web::{http::header, HttpRequest};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime, Document},
+    options::{FindOptions, IndexOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::FileRecord,
+    utils::{AppState, CustomError},
+};
+
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    LoginSucceeded,
+    LoginFailed,
+    Signup,
+    Upload,
+    Download,
+    Trash,
+    Delete,
+    ShareCreated,
+    ShareRevoked,
+    GrantChanged,
+    GrantRevoked,
+    QuotaChanged,
+}
+
+/// One entry of the audit log. `actor` is the account that made the request,
+/// which is unknown for failed logins and public share downloads, and `owner`
+/// is the account whose data or credentials were involved. Records are never
+/// updated, and only removed once they pass `expires`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub at: BsonDateTime,
+    pub event: AuditEvent,
+    pub actor: Option<String>,
+    pub owner: Option<String>,
+    pub entry: Option<ObjectId>,
+    pub name: Option<String>,
+    pub detail: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub expires: Option<BsonDateTime>,
+}
+
+impl AuditRecord {
+    pub fn new(data: &AppState, req: &HttpRequest, event: AuditEvent, actor: Option<&str>) -> Self {
+        // Forwarding headers can be set by anyone, so they are only believed
+        // behind a proxy that overwrites them.
+        let ip = if data.opt.trust_proxy {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(str::to_owned)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        };
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        AuditRecord {
+            id: ObjectId::new(),
+            at: BsonDateTime::now(),
+            event,
+            actor: actor.map(str::to_owned),
+            owner: actor.map(str::to_owned),
+            entry: None,
+            name: None,
+            detail: None,
+            ip,
+            user_agent,
+            expires: None,
+        }
+    }
+
+    pub fn with_entry(mut self, record: &FileRecord) -> Self {
+        self.owner = Some(record.owner.clone());
+        self.entry = Some(record.id);
+        self.name = Some(record.name.clone());
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Option<&str>) -> Self {
+        self.owner = owner.map(str::to_owned);
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditMetadata {
+    id: String,
+    at: DateTime<Utc>,
+    event: AuditEvent,
+    actor: Option<String>,
+    owner: Option<String>,
+    entry: Option<String>,
+    name: Option<String>,
+    detail: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl From<&AuditRecord> for AuditMetadata {
+    fn from(record: &AuditRecord) -> Self {
+        AuditMetadata {
+            id: record.id.to_hex(),
+            at: record.at.to_chrono(),
+            event: record.event,
+            actor: record.actor.clone(),
+            owner: record.owner.clone(),
+            entry: record.entry.map(|entry| entry.to_hex()),
+            name: record.name.clone(),
+            detail: record.detail.clone(),
+            ip: record.ip.clone(),
+            user_agent: record.user_agent.clone(),
+        }
+    }
+}
+
+pub async fn create_indexes(audit: &Collection<AuditRecord>) -> mongodb::error::Result<()> {
+    audit
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"actor": 1, "_id": -1})
+                .build(),
+            None,
+        )
+        .await?;
+    audit
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"owner": 1, "_id": -1})
+                .build(),
+            None,
+        )
+        .await?;
+    // Each record carries its own expiry, so changing the retention doesn't
+    // need the index rebuilt and only applies to new records.
+    audit
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"expires": 1})
+                .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Appends `record` to the audit log. A retention of zero days keeps it forever.
+pub async fn record_event(data: &AppState, mut record: AuditRecord) {
+    if data.opt.audit_retention_days > 0 {
+        let retention = chrono::Duration::days(data.opt.audit_retention_days as i64);
+        record.expires = Some(BsonDateTime::from_chrono(record.at.to_chrono() + retention));
+    }
+    if let Err(e) = data.audit_collection.insert_one(&record, None).await {
+        tracing::error!("Failed to record {:?} audit event: {}", record.event, e);
+    }
+}
+
+/// A page of events, newest first. Without a `user` every event is listed.
+pub async fn list_events(
+    data: &AppState,
+    user: Option<&str>,
+    event: Option<AuditEvent>,
+    before: Option<ObjectId>,
+    limit: u32,
+) -> Result<Vec<AuditRecord>, CustomError> {
+    let mut filter = Document::new();
+    if let Some(user) = user {
+        filter.insert("$or", vec![doc! {"actor": user}, doc! {"owner": user}]);
+    }
+    if let Some(event) = event {
+        let event = mongodb::bson::to_bson(&event).map_err(|_| CustomError::InternalError)?;
+        filter.insert("event", event);
+    }
+    if let Some(before) = before {
+        filter.insert("_id", doc! {"$lt": before});
+    }
+    let options = FindOptions::builder()
+        .sort(doc! {"_id": -1})
+        .limit(limit as i64)
+        .build();
+    Ok(data
+        .audit_collection
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
@@ -0,0 +1,239 @@
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, Bson, DateTime as BsonDateTime, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::{
+    metadata::FileRecord,
+    staging::{StagedFile, StagingKey},
+    storage::Storage,
+    utils::CustomError,
+};
+
+const ACQUIRE_ATTEMPTS: u32 = 5;
+const GC_GRACE: Duration = Duration::from_secs(60 * 60);
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobRecord {
+    #[serde(rename = "_id")]
+    pub hash: String,
+    pub size: i64,
+    pub refs: i64,
+    pub created: BsonDateTime,
+    pub released: Option<BsonDateTime>,
+    #[serde(default)]
+    pub deleting: bool,
+    /// Whether the content is in storage yet. A blob is counted as soon as
+    /// its first upload starts, so later uploads of the same content have to
+    /// check this before relying on it.
+    #[serde(default = "stored_default")]
+    pub stored: bool,
+}
+
+fn stored_default() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct DedupStats {
+    blobs: u64,
+    referenced_bytes: i64,
+    stored_bytes: i64,
+    saved_bytes: i64,
+}
+
+pub fn blob_key(hash: &str) -> String {
+    format!("blobs/{}", hash)
+}
+
+pub async fn create_indexes(blobs: &Collection<BlobRecord>) -> mongodb::error::Result<()> {
+    blobs
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"refs": 1, "released": 1})
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn hash_file(key: Option<&StagingKey>, path: &Path) -> io::Result<String> {
+    let mut file = StagedFile::open(key, path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub async fn acquire_blob(
+    blobs: &Collection<BlobRecord>,
+    storage: &dyn Storage,
+    staged_path: &Path,
+    hash: &str,
+) -> Result<String, CustomError> {
+    let size = fs::metadata(staged_path).await?.len() as i64;
+    let hash = hash.to_owned();
+    for _ in 0..ACQUIRE_ATTEMPTS {
+        let result = blobs
+            .find_one_and_update(
+                doc! {"_id": &hash, "deleting": false},
+                doc! {
+                    "$inc": {"refs": 1},
+                    "$set": {"released": null},
+                    "$setOnInsert": {"size": size, "created": BsonDateTime::now(), "stored": false},
+                },
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::Before)
+                    .build(),
+            )
+            .await;
+        match result {
+            Ok(Some(blob)) if blob.stored => {
+                fs::remove_file(staged_path).await.ok();
+                return Ok(hash);
+            }
+            // Either this is the first copy, or the first upload hasn't
+            // finished storing it. The content is the same either way, so
+            // storing our own copy is safe and doesn't wait on the other one.
+            Ok(_) => {
+                if let Err(e) = storage.put_file(&blob_key(&hash), staged_path).await {
+                    release_blob(blobs, &hash).await.ok();
+                    return Err(e.into());
+                }
+                blobs
+                    .update_one(doc! {"_id": &hash}, doc! {"$set": {"stored": true}}, None)
+                    .await?;
+                return Ok(hash);
+            }
+            Err(e) => match CustomError::from(e) {
+                CustomError::Conflict => tokio::time::sleep(Duration::from_millis(200)).await,
+                e => return Err(e),
+            },
+        }
+    }
+    Err(CustomError::InternalError)
+}
+
+pub async fn retain_blob(blobs: &Collection<BlobRecord>, hash: &str) -> Result<(), CustomError> {
+    let result = blobs
+        .update_one(
+            doc! {"_id": hash, "deleting": false},
+            doc! {"$inc": {"refs": 1}, "$set": {"released": null}},
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(CustomError::MissingPath);
+    }
+    Ok(())
+}
+
+pub async fn release_blob(blobs: &Collection<BlobRecord>, hash: &str) -> Result<(), CustomError> {
+    blobs
+        .update_one(doc! {"_id": hash}, doc! {"$inc": {"refs": -1}}, None)
+        .await?;
+    blobs
+        .update_one(
+            doc! {"_id": hash, "refs": {"$lte": 0}, "released": null},
+            doc! {"$set": {"released": BsonDateTime::now()}},
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn aggregate_totals<T>(
+    collection: &Collection<T>,
+    filter: Document,
+) -> Result<(i64, u64), CustomError> {
+    let totals: Vec<Document> = collection
+        .clone_with_type::<Document>()
+        .aggregate(
+            vec![
+                doc! {"$match": filter},
+                doc! {"$group": {"_id": null, "size": {"$sum": "$size"}, "count": {"$sum": 1}}},
+            ],
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    let number = |totals: &Document, key: &str| match totals.get(key) {
+        Some(Bson::Int32(value)) => *value as i64,
+        Some(Bson::Int64(value)) => *value,
+        Some(Bson::Double(value)) => *value as i64,
+        _ => 0,
+    };
+    Ok(totals
+        .first()
+        .map(|totals| (number(totals, "size"), number(totals, "count") as u64))
+        .unwrap_or_default())
+}
+
+pub async fn dedup_stats(
+    files: &Collection<FileRecord>,
+    blobs: &Collection<BlobRecord>,
+) -> Result<DedupStats, CustomError> {
+    let (referenced_bytes, _) = aggregate_totals(files, doc! {"blob": {"$ne": null}}).await?;
+    let (stored_bytes, blob_count) =
+        aggregate_totals(blobs, doc! {"refs": {"$gt": 0}, "stored": {"$ne": false}}).await?;
+    Ok(DedupStats {
+        blobs: blob_count,
+        referenced_bytes,
+        stored_bytes,
+        saved_bytes: referenced_bytes - stored_bytes,
+    })
+}
+
+async fn remove_unreferenced_blobs(
+    blobs: &Collection<BlobRecord>,
+    storage: &dyn Storage,
+) -> Result<u64, CustomError> {
+    let cutoff = BsonDateTime::from_millis(
+        BsonDateTime::now().timestamp_millis() - GC_GRACE.as_millis() as i64,
+    );
+    let mut removed = 0;
+    while let Some(blob) = blobs
+        .find_one_and_update(
+            doc! {"refs": {"$lte": 0}, "released": {"$lt": cutoff}, "deleting": false},
+            doc! {"$set": {"deleting": true}},
+            None,
+        )
+        .await?
+    {
+        storage.delete(&blob_key(&blob.hash)).await?;
+        blobs
+            .delete_one(doc! {"_id": &blob.hash, "deleting": true}, None)
+            .await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+pub async fn collect_blobs(blobs: Collection<BlobRecord>, storage: Arc<dyn Storage>) {
+    let mut interval = tokio::time::interval(GC_INTERVAL);
+    loop {
+        interval.tick().await;
+        match remove_unreferenced_blobs(&blobs, storage.as_ref()).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("Removed {} unreferenced blobs", removed),
+            Err(e) => tracing::warn!("Failed to remove unreferenced blobs: {}", e),
+        }
+    }
+}
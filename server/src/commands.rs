@@ -0,0 +1,111 @@
+use std::{env, io, path::Path, sync::Arc};
+
+use futures_util::TryStreamExt;
+use mongodb::bson::Document;
+
+use crate::{
+    blobs::BlobRecord,
+    keys::{parse_master_key, rotate_master_key, KeyRecord},
+    metadata::{import_untracked, FileRecord},
+    scrub::scrub,
+    staging::rotate_staging_key,
+    text_index::{index_records, TextIndex},
+    utils::{connect, open_storage, Command, Config, Opt},
+    versions::VersionRecord,
+};
+
+fn invalid_key(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} must be a base64 encoded 32 byte key", name),
+    )
+}
+
+/// Runs a maintenance command instead of the server.
+pub async fn run(opt: &Opt, command: &Command) -> io::Result<()> {
+    let config = Config::init();
+    match command {
+        Command::RotateMasterKey => {
+            let old = config
+                .master_key
+                .as_deref()
+                .and_then(parse_master_key)
+                .ok_or_else(|| invalid_key("MASTER_KEY"))?;
+            let new = env::var("NEW_MASTER_KEY")
+                .ok()
+                .as_deref()
+                .and_then(parse_master_key)
+                .ok_or_else(|| invalid_key("NEW_MASTER_KEY"))?;
+            let keys = connect(&config).await.collection::<KeyRecord>("keys");
+            let (rotated, skipped) = rotate_master_key(&keys, &old, &new).await?;
+            tracing::info!(
+                "Re-wrapped {} data keys, {} already used the new master key",
+                rotated,
+                skipped
+            );
+            if rotate_staging_key(Path::new(&opt.staging_root), &old, &new)? {
+                tracing::info!("Re-wrapped the staging key");
+            }
+            Ok(())
+        }
+        Command::Scrub { quarantine } => {
+            let database = connect(&config).await;
+            let storage = open_storage(opt, &config, &database);
+            let summary = scrub(
+                &database.collection::<FileRecord>("files"),
+                &database.collection::<VersionRecord>("versions"),
+                &database.collection::<BlobRecord>("blobs"),
+                storage.as_ref(),
+                *quarantine,
+            )
+            .await?;
+            tracing::info!(
+                "Checked {} objects: {} corrupt, {} missing, {} orphaned, {} checksums recorded, {} quarantined",
+                summary.checked,
+                summary.corrupt,
+                summary.missing,
+                summary.orphaned,
+                summary.recorded,
+                summary.quarantined
+            );
+            if summary.problems() > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Scrub found {} problems", summary.problems()),
+                ));
+            }
+            Ok(())
+        }
+        Command::ImportUntracked => {
+            let database = connect(&config).await;
+            let storage = open_storage(opt, &config, &database);
+            let index = Arc::new(
+                TextIndex::open(&opt.index_dir, config.master_key.is_some())
+                    .map_err(io::Error::other)?,
+            );
+            let files = database.collection::<FileRecord>("files");
+            let users: Vec<Document> = database
+                .collection::<Document>("users")
+                .find(None, None)
+                .await
+                .map_err(io::Error::other)?
+                .try_collect()
+                .await
+                .map_err(io::Error::other)?;
+            let mut imported = 0;
+            for user in users {
+                let owner = match user.get_object_id("_id") {
+                    Ok(id) => id.to_hex(),
+                    Err(_) => continue,
+                };
+                let records = import_untracked(&files, storage.as_ref(), &owner)
+                    .await
+                    .map_err(io::Error::other)?;
+                index_records(&index, storage.as_ref(), &records).await;
+                imported += records.len();
+            }
+            tracing::info!("Imported {} untracked files", imported);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use mongodb::bson::Bson;
+use serde::{Deserialize, Serialize};
+
+use crate::staging::{StagedFile, StagingKey};
+
+pub const SNIFF_LENGTH: usize = 8192;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+    Code,
+    #[default]
+    Other,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Image => "image",
+            Category::Video => "video",
+            Category::Audio => "audio",
+            Category::Document => "document",
+            Category::Archive => "archive",
+            Category::Code => "code",
+            Category::Other => "other",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Category::Image,
+            Category::Video,
+            Category::Audio,
+            Category::Document,
+            Category::Archive,
+            Category::Code,
+            Category::Other,
+        ]
+        .into_iter()
+        .find(|category| category.name() == name)
+    }
+
+    pub fn from_mime(mime: &str) -> Self {
+        let (top, sub) = mime.split_once('/').unwrap_or((mime, ""));
+        match (top, sub) {
+            ("image", _) => Category::Image,
+            ("video", _) => Category::Video,
+            ("audio", _) => Category::Audio,
+            ("text", "plain" | "markdown" | "csv" | "rtf") => Category::Document,
+            ("text", _) => Category::Code,
+            ("application", sub) => match sub {
+                "pdf" | "msword" | "rtf" | "epub+zip" | "vnd.ms-excel" | "vnd.ms-powerpoint" => {
+                    Category::Document
+                }
+                sub if sub.starts_with("vnd.openxmlformats-officedocument")
+                    || sub.starts_with("vnd.oasis.opendocument") =>
+                {
+                    Category::Document
+                }
+                "zip" | "gzip" | "x-tar" | "x-7z-compressed" | "vnd.rar" | "x-rar-compressed"
+                | "x-bzip2" | "x-xz" | "zstd" | "x-compress" | "x-lzip" => Category::Archive,
+                "json" | "xml" | "javascript" | "x-javascript" | "x-sh" | "x-python"
+                | "x-httpd-php" | "toml" | "yaml" | "x-yaml" | "sql" => Category::Code,
+                _ => Category::Other,
+            },
+            _ => Category::Other,
+        }
+    }
+}
+
+impl From<Category> for Bson {
+    fn from(category: Category) -> Self {
+        Bson::String(category.name().to_owned())
+    }
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() == SNIFF_LENGTH,
+    }
+}
+
+pub fn detect_mime(head: &[u8], name: &str) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_owned();
+    }
+    if head.is_empty() || !looks_like_text(head) {
+        return mime_guess::mime::APPLICATION_OCTET_STREAM.to_string();
+    }
+    let guessed = mime_guess::from_path(name).first_or_text_plain();
+    match Category::from_mime(guessed.essence_str()) {
+        Category::Document | Category::Code => guessed.essence_str().to_owned(),
+        _ => mime_guess::mime::TEXT_PLAIN.essence_str().to_owned(),
+    }
+}
+
+pub async fn detect_file(
+    key: Option<&StagingKey>,
+    path: &Path,
+    name: &str,
+) -> std::io::Result<String> {
+    let mut file = StagedFile::open(key, path).await?;
+    let mut head = vec![0; SNIFF_LENGTH];
+    let mut filled = 0;
+    while filled < head.len() {
+        let read = file.read(&mut head[filled..]).await?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    head.truncate(filled);
+    Ok(detect_mime(&head, name))
+}
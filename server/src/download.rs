@@ -0,0 +1,512 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::SizedStream,
+    http::{
+        header::{
+            self, Charset, ContentDisposition, DispositionParam, DispositionType, EntityTag,
+            ExtendedValue, Header, HttpDate, IfNoneMatch, IfRange, Range,
+        },
+        Method, StatusCode,
+    },
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+
+use crate::{metadata::FileRecord, storage::Storage};
+
+const MAX_RANGES: usize = 32;
+
+/// Stops browsers from guessing a more dangerous type than the one sent.
+const NO_SNIFF: (header::HeaderName, &str) = (header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+/// Files are served from the app's origin, so anything opened inline gets no
+/// scripts, forms or same-origin access even if its type is misdetected.
+const SANDBOX: (header::HeaderName, &str) = (header::CONTENT_SECURITY_POLICY, "sandbox");
+
+/// Types browsers display without running anything, and which are safe to
+/// show inline.
+const INLINE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "text/plain",
+    "application/pdf",
+];
+
+fn is_inline_safe(mime: &mime_guess::Mime) -> bool {
+    matches!(
+        mime.type_(),
+        mime_guess::mime::VIDEO | mime_guess::mime::AUDIO
+    ) || INLINE_TYPES.contains(&mime.essence_str())
+}
+
+fn served_mime(mime: &mime_guess::Mime) -> mime_guess::Mime {
+    let active = mime.type_() == mime_guess::mime::TEXT
+        || matches!(mime.suffix(), Some(mime_guess::mime::XML))
+        || matches!(
+            mime.essence_str(),
+            "application/xml" | "application/javascript" | "application/ecmascript"
+        );
+    if active {
+        mime_guess::mime::TEXT_PLAIN_UTF_8
+    } else {
+        mime.clone()
+    }
+}
+
+enum RangeRequest {
+    Full,
+    Unsatisfiable,
+    Partial(Vec<(u64, u64)>),
+}
+
+pub struct Download {
+    storage: Arc<dyn Storage>,
+    key: String,
+    file_name: String,
+    size: u64,
+    modified: SystemTime,
+    mime: mime_guess::Mime,
+    checksum: Option<String>,
+}
+
+impl Download {
+    pub fn new(storage: Arc<dyn Storage>, record: &FileRecord) -> Self {
+        Self::with_key(storage, record, record.content_key())
+    }
+
+    pub fn with_key(storage: Arc<dyn Storage>, record: &FileRecord, key: String) -> Self {
+        Download {
+            storage,
+            key,
+            file_name: record.name.clone(),
+            size: record.size as u64,
+            modified: record.modified.to_system_time(),
+            mime: record
+                .mime
+                .parse()
+                .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM),
+            checksum: record.checksum.clone(),
+        }
+    }
+
+    fn etag(&self) -> EntityTag {
+        let modified = self
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        EntityTag::new_strong(format!("{:x}-{:x}", self.size, modified))
+    }
+
+    /// The checksum of the whole file, in the `Digest` header format.
+    fn digest(&self) -> Option<String> {
+        let checksum = hex::decode(self.checksum.as_ref()?).ok()?;
+        Some(format!("sha-256={}", STANDARD.encode(checksum)))
+    }
+
+    fn last_modified(&self) -> HttpDate {
+        HttpDate::from(self.modified)
+    }
+
+    /// The type the content is sent as. Anything a browser could run as a page
+    /// from our origin, like HTML or SVG, is sent as plain text instead.
+    fn served_mime(&self) -> mime_guess::Mime {
+        served_mime(&self.mime)
+    }
+
+    fn content_disposition(&self, attachment: bool) -> ContentDisposition {
+        let disposition = if !attachment && is_inline_safe(&self.mime) {
+            DispositionType::Inline
+        } else {
+            DispositionType::Attachment
+        };
+        file_disposition(disposition, &self.file_name)
+    }
+
+    fn is_not_modified(&self, req: &HttpRequest, etag: &EntityTag) -> bool {
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+                Err(_) => false,
+            };
+        }
+        match header::IfModifiedSince::parse(req) {
+            Ok(header::IfModifiedSince(since)) => {
+                truncate_to_seconds(self.modified) <= SystemTime::from(since)
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn range_applies(&self, req: &HttpRequest, etag: &EntityTag) -> bool {
+        if !req.headers().contains_key(header::IF_RANGE) {
+            return true;
+        }
+        match IfRange::parse(req) {
+            Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+            Ok(IfRange::Date(date)) => truncate_to_seconds(self.modified) == SystemTime::from(date),
+            Err(_) => false,
+        }
+    }
+
+    fn requested_ranges(&self, req: &HttpRequest, etag: &EntityTag) -> RangeRequest {
+        if !req.headers().contains_key(header::RANGE) || !self.range_applies(req, etag) {
+            return RangeRequest::Full;
+        }
+        let specs = match Range::parse(req) {
+            Ok(Range::Bytes(specs)) if specs.len() <= MAX_RANGES => specs,
+            _ => return RangeRequest::Full,
+        };
+        let ranges: Vec<(u64, u64)> = specs
+            .iter()
+            .filter_map(|spec| spec.to_satisfiable_range(self.size))
+            .collect();
+        if ranges.is_empty() {
+            RangeRequest::Unsatisfiable
+        } else {
+            RangeRequest::Partial(ranges)
+        }
+    }
+
+    /// Whether a request reads the file afresh, rather than continuing an
+    /// earlier transfer or only asking for headers. Only a range validated
+    /// with `If-Range` that skips the start of the file continues a transfer,
+    /// so ranges alone can't fetch the whole file without it being counted.
+    pub fn starts_transfer(&self, req: &HttpRequest) -> bool {
+        if req.method() != Method::GET {
+            return false;
+        }
+        let etag = self.etag();
+        if self.is_not_modified(req, &etag) {
+            return false;
+        }
+        if !req.headers().contains_key(header::IF_RANGE) {
+            return true;
+        }
+        match self.requested_ranges(req, &etag) {
+            RangeRequest::Full => true,
+            RangeRequest::Unsatisfiable => false,
+            RangeRequest::Partial(ranges) => ranges.iter().any(|&(start, _)| start == 0),
+        }
+    }
+
+    pub fn into_response(self, req: &HttpRequest, attachment: bool) -> HttpResponse {
+        let etag = self.etag();
+        if self.is_not_modified(req, &etag) {
+            return HttpResponse::NotModified()
+                .insert_header(header::ETag(etag))
+                .insert_header(NO_SNIFF)
+                .insert_header(SANDBOX)
+                .insert_header(header::LastModified(self.last_modified()))
+                .finish();
+        }
+
+        let mut response = HttpResponse::Ok();
+        response
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .insert_header(header::ETag(etag.clone()))
+            .insert_header(header::LastModified(self.last_modified()))
+            .insert_header(self.content_disposition(attachment))
+            .insert_header(NO_SNIFF)
+            .insert_header(SANDBOX);
+        if let Some(digest) = self.digest() {
+            response.insert_header(("Digest", digest));
+        }
+
+        match self.requested_ranges(req, &etag) {
+            RangeRequest::Full => response
+                .insert_header(header::ContentType(self.served_mime()))
+                .body(SizedStream::new(
+                    self.size,
+                    content_slice(self.storage, self.key, 0, self.size),
+                )),
+            RangeRequest::Unsatisfiable => response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", self.size)))
+                .finish(),
+            RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .insert_header(header::ContentType(self.served_mime()))
+                    .insert_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end, self.size),
+                    ))
+                    .body(SizedStream::new(
+                        end - start + 1,
+                        content_slice(self.storage, self.key, start, end - start + 1),
+                    ))
+            }
+            RangeRequest::Partial(ranges) => {
+                let boundary = uuid::Uuid::new_v4().simple().to_string();
+                let mime = self.served_mime();
+                let parts: Vec<(Bytes, u64, u64)> = ranges
+                    .iter()
+                    .map(|&(start, end)| {
+                        let part_header = format!(
+                            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                            boundary, mime, start, end, self.size
+                        );
+                        (Bytes::from(part_header), start, end - start + 1)
+                    })
+                    .collect();
+                let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+                let length = parts
+                    .iter()
+                    .map(|(part_header, _, length)| part_header.len() as u64 + length)
+                    .sum::<u64>()
+                    + closing.len() as u64;
+
+                let (storage, key) = (self.storage, self.key);
+                let body = stream::iter(parts)
+                    .flat_map(move |(part_header, start, length)| {
+                        stream::once(async move { Ok(part_header) }).chain(content_slice(
+                            storage.clone(),
+                            key.clone(),
+                            start,
+                            length,
+                        ))
+                    })
+                    .chain(stream::once(async move { Ok(closing) }));
+
+                response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .insert_header((
+                        header::CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={}", boundary),
+                    ))
+                    .body(SizedStream::new(length, body))
+            }
+        }
+    }
+}
+
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + std::time::Duration::from_secs(seconds)
+}
+
+pub fn file_disposition(disposition: DispositionType, file_name: &str) -> ContentDisposition {
+    let mut parameters = vec![DispositionParam::Filename(
+        file_name
+            .chars()
+            .map(|c| if c.is_ascii() && c != '"' { c } else { '_' })
+            .collect(),
+    )];
+    if !file_name.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext(String::from("UTF-8")),
+            language_tag: None,
+            value: file_name.to_owned().into_bytes(),
+        }));
+    }
+    ContentDisposition {
+        disposition,
+        parameters,
+    }
+}
+
+pub fn content_slice(
+    storage: Arc<dyn Storage>,
+    key: String,
+    start: u64,
+    length: u64,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    stream::once(async move { storage.read(&key, start, length).await }).try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::header, test::TestRequest};
+    use mongodb::bson::DateTime as BsonDateTime;
+
+    use super::*;
+    use crate::{
+        metadata::{EntryKind, FileRecord},
+        storage::memory::MemoryStorage,
+    };
+
+    const CONTENT: &[u8] = b"0123456789";
+
+    async fn download(name: &str) -> Download {
+        let storage = Arc::new(MemoryStorage::default());
+        let mut record = FileRecord::new("user", None, name, EntryKind::File);
+        record.size = CONTENT.len() as i64;
+        record.modified = BsonDateTime::from_millis(1_700_000_000_000);
+        storage
+            .put(&record.content_key(), Bytes::from_static(CONTENT))
+            .await
+            .unwrap();
+        Download::new(storage, &record)
+    }
+
+    async fn respond(request: TestRequest, name: &str) -> (StatusCode, header::HeaderMap, Bytes) {
+        let download = download(name).await;
+        let response = download.into_response(&request.to_http_request(), false);
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, headers, body)
+    }
+
+    #[actix_web::test]
+    async fn serves_the_whole_file_without_a_range() {
+        let (status, headers, body) = respond(TestRequest::get(), "notes.txt").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(&body[..], CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn serves_a_single_range() {
+        let request = TestRequest::get().insert_header((header::RANGE, "bytes=2-5"));
+        let (status, headers, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers.get(header::CONTENT_RANGE).unwrap(), "bytes 2-5/10");
+        assert_eq!(&body[..], b"2345");
+
+        let request = TestRequest::get().insert_header((header::RANGE, "bytes=-3"));
+        let (_, headers, body) = respond(request, "notes.txt").await;
+        assert_eq!(headers.get(header::CONTENT_RANGE).unwrap(), "bytes 7-9/10");
+        assert_eq!(&body[..], b"789");
+
+        let request = TestRequest::get().insert_header((header::RANGE, "bytes=8-"));
+        let (_, _, body) = respond(request, "notes.txt").await;
+        assert_eq!(&body[..], b"89");
+    }
+
+    #[actix_web::test]
+    async fn serves_several_ranges_as_multipart() {
+        let request = TestRequest::get().insert_header((header::RANGE, "bytes=0-1,8-9"));
+        let (status, headers, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let content_type = headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Content-Range: bytes 0-1/10\r\n\r\n01"));
+        assert!(body.contains("Content-Range: bytes 8-9/10\r\n\r\n89"));
+    }
+
+    #[actix_web::test]
+    async fn refuses_ranges_past_the_end() {
+        let request = TestRequest::get().insert_header((header::RANGE, "bytes=20-30"));
+        let (status, headers, _) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers.get(header::CONTENT_RANGE).unwrap(), "bytes */10");
+    }
+
+    #[actix_web::test]
+    async fn ignores_malformed_or_stale_ranges() {
+        let request = TestRequest::get().insert_header((header::RANGE, "lines=1-2"));
+        let (status, _, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], CONTENT);
+
+        let request = TestRequest::get()
+            .insert_header((header::RANGE, "bytes=2-5"))
+            .insert_header((header::IF_RANGE, "\"stale\""));
+        let (status, _, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn honours_conditional_requests() {
+        let (_, headers, _) = respond(TestRequest::get(), "notes.txt").await;
+        let etag = headers.get(header::ETAG).unwrap().clone();
+
+        let request = TestRequest::get().insert_header((header::IF_NONE_MATCH, etag.clone()));
+        let (status, _, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(body.is_empty());
+
+        let request = TestRequest::get()
+            .insert_header((header::RANGE, "bytes=2-5"))
+            .insert_header((header::IF_RANGE, etag));
+        let (status, _, body) = respond(request, "notes.txt").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(&body[..], b"2345");
+    }
+
+    async fn starts_transfer(request: TestRequest) -> bool {
+        let download = download("notes.txt").await;
+        download.starts_transfer(&request.to_http_request())
+    }
+
+    #[actix_web::test]
+    async fn counts_fresh_downloads() {
+        assert!(starts_transfer(TestRequest::get()).await);
+        assert!(!starts_transfer(TestRequest::default().method(Method::HEAD)).await);
+
+        let download = download("notes.txt").await;
+        let etag = download.etag().to_string();
+        let request = TestRequest::get().insert_header((header::IF_NONE_MATCH, etag));
+        assert!(!starts_transfer(request).await);
+    }
+
+    #[actix_web::test]
+    async fn counts_ranges_without_if_range() {
+        // Otherwise the whole file could be fetched a piece at a time.
+        for range in ["bytes=0-4", "bytes=5-9", "bytes=5-"] {
+            let request = TestRequest::get().insert_header((header::RANGE, range));
+            assert!(starts_transfer(request).await, "{}", range);
+        }
+    }
+
+    #[actix_web::test]
+    async fn skips_validated_continuations() {
+        let download = download("notes.txt").await;
+        let etag = download.etag().to_string();
+        let resume = |range: &str| {
+            TestRequest::get()
+                .insert_header((header::RANGE, range.to_owned()))
+                .insert_header((header::IF_RANGE, etag.clone()))
+        };
+        assert!(!starts_transfer(resume("bytes=5-")).await);
+        assert!(starts_transfer(resume("bytes=0-4")).await);
+        assert!(!starts_transfer(resume("bytes=20-")).await);
+
+        let stale = TestRequest::get()
+            .insert_header((header::RANGE, "bytes=5-"))
+            .insert_header((header::IF_RANGE, "\"stale\""));
+        assert!(starts_transfer(stale).await);
+    }
+
+    #[actix_web::test]
+    async fn only_shows_safe_types_inline() {
+        let (_, headers, _) = respond(TestRequest::get(), "photo.png").await;
+        let disposition = headers.get(header::CONTENT_DISPOSITION).unwrap();
+        assert!(disposition.to_str().unwrap().starts_with("inline"));
+        assert_eq!(
+            headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+        assert_eq!(
+            headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+            "sandbox"
+        );
+
+        for name in ["page.html", "drawing.svg", "script.js"] {
+            let (_, headers, _) = respond(TestRequest::get(), name).await;
+            let disposition = headers.get(header::CONTENT_DISPOSITION).unwrap();
+            assert!(disposition.to_str().unwrap().starts_with("attachment"));
+            let content_type = headers.get(header::CONTENT_TYPE).unwrap();
+            assert_eq!(content_type, "text/plain; charset=utf-8");
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::{FindOptions, IndexOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::{EntryKind, FileRecord},
+    utils::{AppState, CustomError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Access to an entry, and everything below it, given by its owner to another account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub owner: String,
+    pub entry: ObjectId,
+    pub grantee: String,
+    pub email: String,
+    pub access: Access,
+    pub created: BsonDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrantMetadata {
+    id: String,
+    entry: String,
+    email: String,
+    access: Access,
+    created: DateTime<Utc>,
+}
+
+impl From<&GrantRecord> for GrantMetadata {
+    fn from(grant: &GrantRecord) -> Self {
+        GrantMetadata {
+            id: grant.id.to_hex(),
+            entry: grant.entry.to_hex(),
+            email: grant.email.clone(),
+            access: grant.access,
+            created: grant.created.to_chrono(),
+        }
+    }
+}
+
+/// A folder, or the root, that new entries are created in. Entries belong to
+/// the owner of the folder, not to whoever created them.
+#[derive(Debug, Clone)]
+pub struct Destination {
+    pub owner: String,
+    pub folder: Option<ObjectId>,
+}
+
+pub async fn create_indexes(grants: &Collection<GrantRecord>) -> mongodb::error::Result<()> {
+    grants
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"entry": 1, "grantee": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    grants
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"grantee": 1, "owner": 1})
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// The strongest access `user` has to `record`, either as its owner or through
+/// a grant on it or one of its ancestors.
+async fn granted_access(
+    data: &AppState,
+    user: &str,
+    record: &FileRecord,
+) -> Result<Option<Access>, CustomError> {
+    if record.owner == user {
+        return Ok(Some(Access::Write));
+    }
+    let grants = &data.grant_collection;
+    if grants
+        .count_documents(doc! {"grantee": user, "owner": &record.owner}, None)
+        .await?
+        == 0
+    {
+        return Ok(None);
+    }
+
+    let mut chain = vec![record.id];
+    let mut current = record.parent;
+    while let Some(id) = current {
+        chain.push(id);
+        current = data
+            .file_collection
+            .find_one(doc! {"_id": id, "owner": &record.owner}, None)
+            .await?
+            .and_then(|parent| parent.parent);
+    }
+    let matching: Vec<GrantRecord> = grants
+        .find(doc! {"grantee": user, "entry": {"$in": chain}}, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(matching.iter().map(|grant| grant.access).max())
+}
+
+/// Finds an entry `user` can use at the given access level. Entries they can't
+/// see at all are reported as missing.
+pub async fn authorize(
+    data: &AppState,
+    user: &str,
+    id: &str,
+    access: Access,
+) -> Result<FileRecord, CustomError> {
+    let id = ObjectId::parse_str(id).map_err(|_| CustomError::MissingPath)?;
+    let record = data
+        .file_collection
+        .find_one(doc! {"_id": id, "trashed": null}, None)
+        .await?
+        .ok_or(CustomError::MissingPath)?;
+    match granted_access(data, user, &record).await? {
+        Some(granted) if granted >= access => Ok(record),
+        Some(_) => Err(CustomError::Forbidden),
+        None => Err(CustomError::MissingPath),
+    }
+}
+
+pub async fn authorize_file(
+    data: &AppState,
+    user: &str,
+    id: &str,
+    access: Access,
+) -> Result<FileRecord, CustomError> {
+    let record = authorize(data, user, id, access).await?;
+    if record.kind != EntryKind::File {
+        return Err(CustomError::MissingPath);
+    }
+    Ok(record)
+}
+
+pub async fn authorize_folder(
+    data: &AppState,
+    user: &str,
+    id: Option<&str>,
+    access: Access,
+) -> Result<Destination, CustomError> {
+    match id.filter(|id| !id.is_empty()) {
+        Some(id) => {
+            let record = authorize(data, user, id, access).await?;
+            if record.kind != EntryKind::Folder {
+                return Err(CustomError::MissingPath);
+            }
+            Ok(Destination {
+                owner: record.owner,
+                folder: Some(record.id),
+            })
+        }
+        None => Ok(Destination {
+            owner: user.to_owned(),
+            folder: None,
+        }),
+    }
+}
+
+pub async fn list_grants(
+    data: &AppState,
+    owner: &str,
+    entry: Option<ObjectId>,
+) -> Result<Vec<GrantRecord>, CustomError> {
+    let mut filter = doc! {"owner": owner};
+    if let Some(entry) = entry {
+        filter.insert("entry", entry);
+    }
+    let options = FindOptions::builder().sort(doc! {"created": -1}).build();
+    Ok(data
+        .grant_collection
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Entries other accounts have shared with `user`, skipping any that are in the trash.
+pub async fn shared_with(
+    data: &AppState,
+    user: &str,
+) -> Result<Vec<(GrantRecord, FileRecord)>, CustomError> {
+    let options = FindOptions::builder().sort(doc! {"created": -1}).build();
+    let grants: Vec<GrantRecord> = data
+        .grant_collection
+        .find(doc! {"grantee": user}, options)
+        .await?
+        .try_collect()
+        .await?;
+    let mut shared = vec![];
+    for grant in grants {
+        if let Some(record) = data
+            .file_collection
+            .find_one(doc! {"_id": grant.entry, "trashed": null}, None)
+            .await?
+        {
+            shared.push((grant, record));
+        }
+    }
+    Ok(shared)
+}
+
+pub async fn remove_grants(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    data.grant_collection
+        .delete_many(doc! {"entry": record.id}, None)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashMap, fmt, io, sync::Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime as BsonDateTime},
+    Collection,
+};
+use serde::{Deserialize, Serialize};
+
+const NONCE_LENGTH: usize = 24;
+const KEY_LENGTH: usize = 32;
+
+/// A data key, encrypted with the master key. Keys are bound to their owner,
+/// so a wrapped key can't be moved to another account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRecord {
+    #[serde(rename = "_id")]
+    pub owner: String,
+    pub wrapped: String,
+    pub created: BsonDateTime,
+    #[serde(default)]
+    pub rotated: Option<BsonDateTime>,
+}
+
+pub fn parse_master_key(encoded: &str) -> Option<Key> {
+    let bytes = STANDARD.decode(encoded.trim()).ok()?;
+    (bytes.len() == KEY_LENGTH).then(|| *Key::from_slice(&bytes))
+}
+
+pub fn wrap_key(master: &Key, owner: &str, key: &Key) -> String {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: key.as_slice(),
+        aad: owner.as_bytes(),
+    };
+    let sealed = XChaCha20Poly1305::new(master)
+        .encrypt(&nonce, payload)
+        .expect("Failed to wrap data key");
+    STANDARD.encode([nonce.as_slice(), &sealed].concat())
+}
+
+pub fn unwrap_key(master: &Key, owner: &str, wrapped: &str) -> Option<Key> {
+    let bytes = STANDARD.decode(wrapped).ok()?;
+    if bytes.len() < NONCE_LENGTH {
+        return None;
+    }
+    let (nonce, sealed) = bytes.split_at(NONCE_LENGTH);
+    let payload = Payload {
+        msg: sealed,
+        aad: owner.as_bytes(),
+    };
+    let key = XChaCha20Poly1305::new(master)
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .ok()?;
+    (key.len() == KEY_LENGTH).then(|| *Key::from_slice(&key))
+}
+
+fn database_error(e: mongodb::error::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Looks up, and creates on first use, the data key of each owner.
+pub struct DataKeys {
+    keys: Collection<KeyRecord>,
+    master: Key,
+    cache: Mutex<HashMap<String, Key>>,
+}
+
+impl fmt::Debug for DataKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataKeys")
+            .field("keys", &self.keys.name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DataKeys {
+    pub fn new(keys: Collection<KeyRecord>, master: Key) -> Self {
+        DataKeys {
+            keys,
+            master,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, owner: &str) -> io::Result<Key> {
+        if let Some(key) = self.cache.lock().unwrap().get(owner) {
+            return Ok(*key);
+        }
+        let key = match self.find(owner).await? {
+            Some(key) => key,
+            None => self.create(owner).await?,
+        };
+        self.cache.lock().unwrap().insert(owner.to_owned(), key);
+        Ok(key)
+    }
+
+    /// Makes `key` the data key of `owner` without going to the database.
+    #[cfg(test)]
+    pub fn preload(&self, owner: &str, key: Key) {
+        self.cache.lock().unwrap().insert(owner.to_owned(), key);
+    }
+
+    async fn find(&self, owner: &str) -> io::Result<Option<Key>> {
+        let record = match self
+            .keys
+            .find_one(doc! {"_id": owner}, None)
+            .await
+            .map_err(database_error)?
+        {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        unwrap_key(&self.master, owner, &record.wrapped)
+            .map(Some)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to unwrap the data key of {}", owner),
+                )
+            })
+    }
+
+    async fn create(&self, owner: &str) -> io::Result<Key> {
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let record = KeyRecord {
+            owner: owner.to_owned(),
+            wrapped: wrap_key(&self.master, owner, &key),
+            created: BsonDateTime::now(),
+            rotated: None,
+        };
+        match self.keys.insert_one(&record, None).await {
+            Ok(_) => Ok(key),
+            // Another request created the key first, use theirs.
+            Err(e) => self.find(owner).await?.ok_or_else(|| database_error(e)),
+        }
+    }
+}
+
+/// Re-wraps every data key with `new`. Keys that already use `new` are skipped,
+/// so an interrupted rotation can simply be run again.
+pub async fn rotate_master_key(
+    keys: &Collection<KeyRecord>,
+    old: &Key,
+    new: &Key,
+) -> io::Result<(u64, u64)> {
+    let records: Vec<KeyRecord> = keys
+        .find(None, None)
+        .await
+        .map_err(database_error)?
+        .try_collect()
+        .await
+        .map_err(database_error)?;
+    let (mut rotated, mut skipped) = (0, 0);
+    for record in records {
+        if unwrap_key(new, &record.owner, &record.wrapped).is_some() {
+            skipped += 1;
+            continue;
+        }
+        let key = unwrap_key(old, &record.owner, &record.wrapped).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The old master key can't unwrap the key of {}",
+                    record.owner
+                ),
+            )
+        })?;
+        keys.update_one(
+            doc! {"_id": &record.owner},
+            doc! {"$set": {
+                "wrapped": wrap_key(new, &record.owner, &key),
+                "rotated": BsonDateTime::now(),
+            }},
+            None,
+        )
+        .await
+        .map_err(database_error)?;
+        rotated += 1;
+    }
+    Ok((rotated, skipped))
+}
@@ -1,25 +1,84 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 
-use actix_web::{get, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{get, guard, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use blobs::collect_blobs;
+use clap::Parser;
 use client::{ServerApp, ServerAppProps};
 use dotenv::dotenv;
 use middleware::AuthenticationFactory;
+use routes::activity::get_recent;
+use routes::archives::{extract_file, get_archive_contents};
+use routes::audit::get_audit_log;
 use routes::auth::{login, signup};
-use routes::files::{get_file_count, get_files_indices};
+use routes::files::{
+    copy_file, create_folder, delete_file, delete_folder, download_archive, download_file,
+    get_dedup_stats, get_file_metadata, get_thumbnail, get_usage, list_files, move_file,
+    rename_file, set_user_quota, upload_files,
+};
+use routes::grants::{create_grant, get_grants, get_shared_with_me, revoke_grant};
+use routes::search::{search_content, search_files};
+use routes::shares::{
+    create_share, download_public_share, get_public_share, list_shares, revoke_share,
+    unlock_public_share,
+};
+use routes::stars::{get_starred, star_file, unstar_file};
+use routes::tags::{add_tags, get_tags, remove_properties, remove_tags, set_properties};
+use routes::trash::{delete_from_trash, empty_trash, get_trash, restore_from_trash};
+use routes::uploads::{
+    append_upload, create_upload, expire_uploads, get_upload_offset, terminate_upload,
+    upload_options,
+};
+use routes::versions::{download_version, get_versions, prune_file_versions, restore_file_version};
+use text_index::rebuild_index;
 use tokio::fs;
+use trash::expire_trash;
+use versions::expire_versions;
 use yew::ServerRenderer;
 
 use crate::middleware::AuthenticationExtractor;
 
 mod routes {
+    pub mod activity;
+    pub mod archives;
+    pub mod audit;
     pub mod auth;
     pub mod files;
+    pub mod grants;
+    pub mod search;
+    pub mod shares;
+    pub mod stars;
+    pub mod tags;
+    pub mod trash;
+    pub mod uploads;
+    pub mod versions;
 }
+mod activity;
+mod archives;
+mod audit;
+mod blobs;
+mod commands;
+mod detect;
+mod download;
+mod grants;
+mod keys;
+mod metadata;
 mod middleware;
+mod quota;
+mod scrub;
+mod shares;
+mod staging;
+mod stars;
+mod storage;
+mod tags;
+mod text_index;
+mod thumbnails;
+mod trash;
 mod utils;
+mod versions;
+mod zip_stream;
 
-use utils::AppState;
+use utils::{AppState, Opt};
 
 #[get("/{tail:.*}")]
 async fn app(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
@@ -47,17 +106,44 @@ async fn api(auth: AuthenticationExtractor) -> impl Responder {
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
-    let state = AppState::init().await;
+    let opt = Opt::parse();
 
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var(
             "RUST_LOG",
-            format!("{},hyper=info,mio=info", &state.opt.log_level),
+            format!("{},hyper=info,mio=info", &opt.log_level),
         )
     }
 
     tracing_subscriber::fmt::init();
 
+    if let Some(command) = &opt.command {
+        return commands::run(&opt, command).await;
+    }
+
+    let state = AppState::init().await;
+
+    actix_web::rt::spawn(expire_uploads(
+        state.opt.staging_root.clone(),
+        state.opt.upload_expiration,
+    ));
+    actix_web::rt::spawn(collect_blobs(
+        state.blob_collection.clone(),
+        state.storage.clone(),
+    ));
+    actix_web::rt::spawn(expire_trash(state.clone(), state.opt.trash_retention));
+    actix_web::rt::spawn(expire_versions(
+        state.clone(),
+        state.opt.version_retention_days,
+    ));
+    if state.text_index.is_empty() {
+        actix_web::rt::spawn(rebuild_index(
+            state.file_collection.clone(),
+            state.storage.clone(),
+            state.text_index.clone(),
+        ));
+    }
+
     let addr = SocketAddr::from((
         IpAddr::from_str(&state.opt.addr).unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
         state.opt.port,
@@ -67,11 +153,69 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(web::Data::new(state.clone()))
             .service(web::scope("/account").service(login).service(signup))
+            .service(
+                web::scope("/share")
+                    .service(get_public_share)
+                    .service(unlock_public_share)
+                    .service(download_public_share),
+            )
+            // Clients ask what the tus endpoint supports before they log in.
+            .service(
+                web::scope("/api")
+                    .guard(guard::Options())
+                    .service(upload_options),
+            )
             .service(
                 web::scope("/api")
                     .wrap(AuthenticationFactory::new())
-                    .service(get_file_count)
-                    .service(get_files_indices)
+                    .service(list_files)
+                    .service(get_file_metadata)
+                    .service(create_folder)
+                    .service(delete_folder)
+                    .service(upload_files)
+                    .service(download_file)
+                    .service(download_archive)
+                    .service(get_archive_contents)
+                    .service(extract_file)
+                    .service(get_thumbnail)
+                    .service(delete_file)
+                    .service(rename_file)
+                    .service(move_file)
+                    .service(copy_file)
+                    .service(get_dedup_stats)
+                    .service(get_usage)
+                    .service(set_user_quota)
+                    .service(search_files)
+                    .service(search_content)
+                    .service(get_trash)
+                    .service(restore_from_trash)
+                    .service(delete_from_trash)
+                    .service(empty_trash)
+                    .service(get_versions)
+                    .service(download_version)
+                    .service(restore_file_version)
+                    .service(prune_file_versions)
+                    .service(create_share)
+                    .service(list_shares)
+                    .service(revoke_share)
+                    .service(create_grant)
+                    .service(get_grants)
+                    .service(revoke_grant)
+                    .service(get_shared_with_me)
+                    .service(add_tags)
+                    .service(remove_tags)
+                    .service(set_properties)
+                    .service(remove_properties)
+                    .service(get_tags)
+                    .service(star_file)
+                    .service(unstar_file)
+                    .service(get_starred)
+                    .service(get_recent)
+                    .service(get_audit_log)
+                    .service(create_upload)
+                    .service(get_upload_offset)
+                    .service(append_upload)
+                    .service(terminate_upload)
                     .service(web::scope("/test").service(api)),
             )
             .service(actix_files::Files::new(
@@ -0,0 +1,697 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime as BsonDateTime, Document},
+    options::{Collation, CollationStrength, FindOptions, IndexOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    activity::remove_activity,
+    blobs::{acquire_blob, blob_key, hash_file, release_blob, retain_blob},
+    detect::{detect_file, detect_mime, Category, SNIFF_LENGTH},
+    grants::remove_grants,
+    quota::check_quota,
+    shares::remove_shares,
+    staging::StagingKey,
+    stars::remove_stars,
+    storage::Storage,
+    text_index::{index_record, unindex_record},
+    thumbnails::remove_thumbnails,
+    utils::{numbered_name, validate_file_name, AppState, ConflictPolicy, CustomError},
+    versions::{
+        archive_version, keeps_versions, prune_versions, remove_versions, unarchive_version,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Folder,
+    File,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub owner: String,
+    pub parent: Option<ObjectId>,
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: i64,
+    pub mime: String,
+    #[serde(default)]
+    pub category: Category,
+    pub created: BsonDateTime,
+    pub modified: BsonDateTime,
+    #[serde(default)]
+    pub blob: Option<String>,
+    /// Hex SHA-256 of the content, missing on files stored before checksums were kept.
+    #[serde(default)]
+    pub checksum: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<BsonDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash: Option<TrashOrigin>,
+}
+
+/// Where a trashed entry lived before it was deleted. Only set on the entry the
+/// user deleted, its descendants just share its `trashed` timestamp.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashOrigin {
+    pub parent: Option<ObjectId>,
+    pub path: String,
+}
+
+impl FileRecord {
+    pub fn new(owner: &str, parent: Option<ObjectId>, name: &str, kind: EntryKind) -> Self {
+        let now = BsonDateTime::now();
+        let mime = match kind {
+            EntryKind::Folder => String::new(),
+            EntryKind::File => mime_guess::from_path(name)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+        FileRecord {
+            id: ObjectId::new(),
+            owner: owner.to_owned(),
+            parent,
+            name: name.to_owned(),
+            kind,
+            size: 0,
+            category: Category::from_mime(&mime),
+            mime,
+            created: now,
+            modified: now,
+            blob: None,
+            checksum: None,
+            tags: vec![],
+            properties: BTreeMap::new(),
+            trashed: None,
+            trash: None,
+        }
+    }
+
+    pub fn content_key(&self) -> String {
+        match &self.blob {
+            Some(hash) => blob_key(hash),
+            None => content_key(&self.owner, self.id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileMetadata {
+    id: String,
+    parent: Option<String>,
+    name: String,
+    kind: EntryKind,
+    size: i64,
+    mime: String,
+    category: Category,
+    extension: String,
+    checksum: Option<String>,
+    tags: Vec<String>,
+    properties: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starred: Option<bool>,
+    created: DateTime<Utc>,
+    modified: DateTime<Utc>,
+}
+
+impl From<&FileRecord> for FileMetadata {
+    fn from(record: &FileRecord) -> Self {
+        FileMetadata {
+            id: record.id.to_hex(),
+            parent: record.parent.map(|parent| parent.to_hex()),
+            name: record.name.clone(),
+            kind: record.kind,
+            size: record.size,
+            mime: record.mime.clone(),
+            category: record.category,
+            extension: match record.kind {
+                EntryKind::Folder => String::new(),
+                EntryKind::File => Path::new(&record.name)
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            },
+            checksum: record.checksum.clone(),
+            tags: record.tags.clone(),
+            properties: record.properties.clone(),
+            starred: None,
+            created: record.created.to_chrono(),
+            modified: record.modified.to_chrono(),
+        }
+    }
+}
+
+impl FileMetadata {
+    /// Stars are per user, so only listings made for a user say whether an entry is starred.
+    pub fn with_starred(mut self, starred: bool) -> Self {
+        self.starred = Some(starred);
+        self
+    }
+}
+
+pub fn content_key(owner: &str, id: ObjectId) -> String {
+    format!("{}/{}", owner, id.to_hex())
+}
+
+pub async fn create_indexes(files: &Collection<FileRecord>) -> mongodb::error::Result<()> {
+    // Trashed entries keep their name and parent, so uniqueness now also covers `trashed`.
+    files.drop_index("owner_1_parent_1_name_1", None).await.ok();
+    files
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"owner": 1, "parent": 1, "name": 1, "trashed": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    files
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"owner": 1, "trash.parent": 1})
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn backfill_categories(files: &Collection<FileRecord>) -> mongodb::error::Result<()> {
+    let filter = doc! {"kind": "file", "category": {"$exists": false}};
+    for mime in files.distinct("mime", filter.clone(), None).await? {
+        let category = Category::from_mime(mime.as_str().unwrap_or_default());
+        let mut filter = filter.clone();
+        filter.insert("mime", mime);
+        files
+            .update_many(filter, doc! {"$set": {"category": category}}, None)
+            .await?;
+    }
+    Ok(())
+}
+
+pub fn listing_options() -> FindOptions {
+    FindOptions::builder()
+        .sort(doc! {"kind": -1, "name": 1, "_id": 1})
+        .collation(
+            Collation::builder()
+                .locale("en")
+                .strength(CollationStrength::Secondary)
+                .build(),
+        )
+        .build()
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, CustomError> {
+    ObjectId::parse_str(id).map_err(|_| CustomError::MissingPath)
+}
+
+pub async fn find_entry(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    id: &str,
+) -> Result<FileRecord, CustomError> {
+    files
+        .find_one(
+            doc! {"_id": parse_id(id)?, "owner": owner, "trashed": null},
+            None,
+        )
+        .await?
+        .ok_or(CustomError::MissingPath)
+}
+
+pub async fn find_trashed(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    id: &str,
+) -> Result<FileRecord, CustomError> {
+    files
+        .find_one(
+            doc! {"_id": parse_id(id)?, "owner": owner, "trash": {"$ne": null}},
+            None,
+        )
+        .await?
+        .ok_or(CustomError::MissingPath)
+}
+
+pub async fn find_file(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    id: &str,
+) -> Result<FileRecord, CustomError> {
+    let record = find_entry(files, owner, id).await?;
+    if record.kind != EntryKind::File {
+        return Err(CustomError::MissingPath);
+    }
+    Ok(record)
+}
+
+pub fn children_filter(owner: &str, parent: Option<ObjectId>) -> Document {
+    doc! {"owner": owner, "parent": parent, "trashed": null}
+}
+
+pub fn type_filter(types: &str) -> Option<Document> {
+    let conditions: Vec<Bson> = types
+        .split(',')
+        .map(str::trim)
+        .filter(|file_type| !file_type.is_empty())
+        .map(|file_type| {
+            if let Some(category) = Category::parse(file_type) {
+                Bson::Document(doc! {"category": category})
+            } else if file_type.contains('/') {
+                Bson::Document(doc! {"mime": file_type})
+            } else {
+                Bson::Document(doc! {"mime": {"$regex": format!("^{}/", regex_escape(file_type))}})
+            }
+        })
+        .collect();
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(doc! {"$or": conditions})
+    }
+}
+
+pub fn regex_escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| {
+            if "\\^$.|?*+()[]{}-/".contains(c) {
+                vec!['\\', c]
+            } else {
+                vec![c]
+            }
+        })
+        .collect()
+}
+
+pub async fn find_child(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    parent: Option<ObjectId>,
+    name: &str,
+) -> Result<Option<FileRecord>, CustomError> {
+    let mut filter = children_filter(owner, parent);
+    filter.insert("name", name);
+    Ok(files.find_one(filter, None).await?)
+}
+
+pub enum Target {
+    Create(String),
+    Replace(Box<FileRecord>),
+}
+
+impl ConflictPolicy {
+    pub async fn resolve(
+        &self,
+        files: &Collection<FileRecord>,
+        owner: &str,
+        parent: Option<ObjectId>,
+        name: &str,
+    ) -> Result<Target, CustomError> {
+        let existing = match find_child(files, owner, parent, name).await? {
+            Some(existing) => existing,
+            None => return Ok(Target::Create(name.to_owned())),
+        };
+        match self {
+            ConflictPolicy::Fail => Err(CustomError::Conflict),
+            ConflictPolicy::Overwrite if existing.kind == EntryKind::Folder => {
+                Err(CustomError::Conflict)
+            }
+            ConflictPolicy::Overwrite => Ok(Target::Replace(Box::new(existing))),
+            ConflictPolicy::Rename => {
+                for n in 1.. {
+                    let candidate = numbered_name(name, n);
+                    if find_child(files, owner, parent, &candidate)
+                        .await?
+                        .is_none()
+                    {
+                        return Ok(Target::Create(candidate));
+                    }
+                }
+                unreachable!()
+            }
+        }
+    }
+}
+
+pub enum Content<'a> {
+    Staged(&'a Path),
+    Copy(&'a FileRecord),
+}
+
+impl Content<'_> {
+    async fn size(&self) -> Result<i64, CustomError> {
+        match self {
+            Content::Staged(path) => Ok(fs::metadata(path).await?.len() as i64),
+            Content::Copy(source) => Ok(source.size),
+        }
+    }
+
+    async fn checksum(&self, key: Option<&StagingKey>) -> Result<Option<String>, CustomError> {
+        match self {
+            Content::Staged(path) => Ok(Some(hash_file(key, path).await?)),
+            Content::Copy(source) => Ok(source.checksum.clone()),
+        }
+    }
+
+    async fn write(
+        &self,
+        data: &AppState,
+        record: &FileRecord,
+    ) -> Result<Option<String>, CustomError> {
+        let storage = data.storage.as_ref();
+        let key = content_key(&record.owner, record.id);
+        match self {
+            Content::Staged(path) if data.opt.dedup => {
+                let hash = record
+                    .checksum
+                    .as_deref()
+                    .ok_or(CustomError::InternalError)?;
+                Ok(Some(
+                    acquire_blob(&data.blob_collection, storage, path, hash).await?,
+                ))
+            }
+            Content::Staged(path) => {
+                storage.put_file(&key, path).await?;
+                Ok(None)
+            }
+            Content::Copy(FileRecord {
+                blob: Some(hash), ..
+            }) => {
+                retain_blob(&data.blob_collection, hash).await?;
+                Ok(Some(hash.clone()))
+            }
+            Content::Copy(source) => {
+                storage.copy(&source.content_key(), &key).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn mime(&self, key: Option<&StagingKey>, name: &str) -> Result<String, CustomError> {
+        match self {
+            Content::Staged(path) => Ok(detect_file(key, path, name).await?),
+            Content::Copy(source) => Ok(source.mime.clone()),
+        }
+    }
+
+    async fn discard(&self) {
+        if let Content::Staged(path) = self {
+            fs::remove_file(path).await.ok();
+        }
+    }
+}
+
+async fn remove_content(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    match &record.blob {
+        Some(hash) => release_blob(&data.blob_collection, hash).await,
+        None => {
+            data.storage.delete(&record.content_key()).await.ok();
+            Ok(())
+        }
+    }
+}
+
+pub async fn store_content(
+    data: &AppState,
+    owner: &str,
+    parent: Option<ObjectId>,
+    name: &str,
+    conflict: ConflictPolicy,
+    content: Content<'_>,
+) -> Result<FileRecord, CustomError> {
+    let files = &data.file_collection;
+    let size = content.size().await?;
+    let mime = content.mime(data.staging_key.as_ref(), name).await?;
+    let checksum = match content.checksum(data.staging_key.as_ref()).await {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            content.discard().await;
+            return Err(e);
+        }
+    };
+    let target = match conflict.resolve(files, owner, parent, name).await {
+        Ok(target) => target,
+        Err(e) => {
+            content.discard().await;
+            return Err(e);
+        }
+    };
+    let (mut record, replaced) = match target {
+        Target::Create(name) => (FileRecord::new(owner, parent, &name, EntryKind::File), None),
+        Target::Replace(record) => {
+            let previous = record.clone();
+            (*record, Some(*previous))
+        }
+    };
+    // With history enabled the replaced content becomes a version and still counts.
+    let freed = match &replaced {
+        Some(previous) if !keeps_versions(data) => previous.size,
+        _ => 0,
+    };
+    if let Err(e) = check_quota(data, owner, size - freed).await {
+        content.discard().await;
+        return Err(e);
+    }
+
+    let archived = match &replaced {
+        Some(previous) if keeps_versions(data) => match archive_version(data, previous).await {
+            Ok(version) => Some(version),
+            Err(e) => {
+                content.discard().await;
+                return Err(e);
+            }
+        },
+        _ => None,
+    };
+    record.checksum = checksum;
+    record.blob = match content.write(data, &record).await {
+        Ok(blob) => blob,
+        Err(e) => {
+            content.discard().await;
+            if let Some(version) = &archived {
+                unarchive_version(data, version).await;
+            }
+            return Err(e);
+        }
+    };
+    record.size = size;
+    record.category = Category::from_mime(&mime);
+    record.mime = mime;
+    match replaced {
+        None => {
+            if let Err(e) = files.insert_one(&record, None).await {
+                remove_content(data, &record).await.ok();
+                return Err(e.into());
+            }
+        }
+        Some(previous) => {
+            record.modified = BsonDateTime::now();
+            files
+                .update_one(
+                    doc! {"_id": record.id},
+                    doc! {"$set": {
+                        "size": record.size,
+                        "mime": &record.mime,
+                        "category": record.category,
+                        "modified": record.modified,
+                        "blob": &record.blob,
+                        "checksum": &record.checksum,
+                    }},
+                    None,
+                )
+                .await?;
+            if archived.is_some() {
+                prune_versions(data, &record, data.opt.keep_versions, None).await?;
+            } else if previous.blob.is_some() || previous.content_key() != record.content_key() {
+                remove_content(data, &previous).await?;
+            }
+            remove_thumbnails(data.storage.as_ref(), &previous).await;
+        }
+    }
+    index_record(data, &record).await;
+    Ok(record)
+}
+
+pub async fn remove_entry(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    data.file_collection
+        .delete_one(doc! {"_id": record.id}, None)
+        .await?;
+    if record.kind == EntryKind::File {
+        remove_content(data, record).await?;
+        remove_versions(data, record).await?;
+        remove_shares(data, record).await?;
+        remove_thumbnails(data.storage.as_ref(), record).await;
+        unindex_record(data, record).await;
+    }
+    remove_grants(data, record).await?;
+    remove_stars(data, record).await?;
+    remove_activity(data, record).await?;
+    Ok(())
+}
+
+pub async fn descendants(
+    files: &Collection<FileRecord>,
+    folder: &FileRecord,
+) -> Result<Vec<FileRecord>, CustomError> {
+    let mut found = vec![];
+    let mut pending = vec![folder.id];
+    while let Some(parent) = pending.pop() {
+        let filter = doc! {"owner": &folder.owner, "parent": parent, "trashed": folder.trashed};
+        let children: Vec<FileRecord> = files.find(filter, None).await?.try_collect().await?;
+        for child in children {
+            if child.kind == EntryKind::Folder {
+                pending.push(child.id);
+            }
+            found.push(child);
+        }
+    }
+    Ok(found)
+}
+
+pub async fn is_within(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    folder: Option<ObjectId>,
+    ancestor: ObjectId,
+) -> Result<bool, CustomError> {
+    let mut current = folder;
+    while let Some(id) = current {
+        if id == ancestor {
+            return Ok(true);
+        }
+        current = files
+            .find_one(doc! {"_id": id, "owner": owner}, None)
+            .await?
+            .and_then(|record| record.parent);
+    }
+    Ok(false)
+}
+
+pub async fn folder_path(
+    files: &Collection<FileRecord>,
+    owner: &str,
+    folder: Option<ObjectId>,
+    cache: &mut HashMap<ObjectId, String>,
+) -> Result<String, CustomError> {
+    let Some(folder) = folder else {
+        return Ok("/".to_owned());
+    };
+    if let Some(path) = cache.get(&folder) {
+        return Ok(path.clone());
+    }
+
+    let mut names = vec![];
+    let mut current = Some(folder);
+    let mut prefix = String::new();
+    while let Some(id) = current {
+        if let Some(path) = cache.get(&id) {
+            prefix = path.trim_end_matches('/').to_owned();
+            break;
+        }
+        let record = files
+            .find_one(doc! {"_id": id, "owner": owner}, None)
+            .await?
+            .ok_or(CustomError::MissingPath)?;
+        names.push(record.name);
+        current = record.parent;
+    }
+    names.reverse();
+    let path = format!("{}/{}/", prefix, names.join("/"));
+    cache.insert(folder, path.clone());
+    Ok(path)
+}
+
+pub async fn import_untracked(
+    files: &Collection<FileRecord>,
+    storage: &dyn Storage,
+    owner: &str,
+) -> Result<Vec<FileRecord>, CustomError> {
+    let prefix = format!("{}/", owner);
+    let mut folders: HashMap<String, ObjectId> = HashMap::new();
+    let mut imported = vec![];
+    for object in storage.list(&prefix).await? {
+        let relative = match object.key.strip_prefix(&prefix) {
+            Some(relative) => relative,
+            None => continue,
+        };
+        // Content the server stores is named after its record, so anything
+        // named like one is ours, even when its record is gone.
+        if ObjectId::parse_str(relative).is_ok() {
+            continue;
+        }
+        let mut components: Vec<&str> = relative.split('/').collect();
+        let name = match components.pop().map(validate_file_name) {
+            Some(Ok(name)) => name.to_owned(),
+            _ => continue,
+        };
+        if components
+            .iter()
+            .any(|component| validate_file_name(component).is_err())
+        {
+            continue;
+        }
+
+        let mut parent = None;
+        for depth in 1..=components.len() {
+            let folder_path = components[..depth].join("/");
+            parent = match folders.get(&folder_path) {
+                Some(id) => Some(*id),
+                None => {
+                    let folder_name = match ConflictPolicy::Rename
+                        .resolve(files, owner, parent, components[depth - 1].trim())
+                        .await?
+                    {
+                        Target::Create(name) => name,
+                        Target::Replace(_) => unreachable!(),
+                    };
+                    let record = FileRecord::new(owner, parent, &folder_name, EntryKind::Folder);
+                    files.insert_one(&record, None).await?;
+                    folders.insert(folder_path, record.id);
+                    Some(record.id)
+                }
+            };
+        }
+
+        let name = match ConflictPolicy::Rename
+            .resolve(files, owner, parent, &name)
+            .await?
+        {
+            Target::Create(name) => name,
+            Target::Replace(_) => continue,
+        };
+        let mut record = FileRecord::new(owner, parent, &name, EntryKind::File);
+        record.size = object.size as i64;
+        record.modified = BsonDateTime::from_system_time(object.modified);
+        let head: Vec<Bytes> = storage
+            .read(&object.key, 0, SNIFF_LENGTH as u64)
+            .await?
+            .try_collect()
+            .await?;
+        record.mime = detect_mime(&head.concat(), &name);
+        record.category = Category::from_mime(&record.mime);
+        storage.rename(&object.key, &record.content_key()).await?;
+        files.insert_one(&record, None).await?;
+        imported.push(record);
+    }
+    Ok(imported)
+}
@@ -0,0 +1,128 @@
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use serde::Serialize;
+
+use crate::utils::{AppState, CustomError};
+
+#[derive(Debug, Serialize)]
+pub struct TypeUsage {
+    #[serde(rename = "type")]
+    file_type: String,
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub used: u64,
+    pub limit: Option<u64>,
+    pub versions: u64,
+    pub breakdown: Vec<TypeUsage>,
+}
+
+fn as_u64(value: Option<&Bson>) -> Option<u64> {
+    match value {
+        Some(Bson::Int32(value)) => Some(*value as u64),
+        Some(Bson::Int64(value)) => Some(*value as u64),
+        Some(Bson::Double(value)) => Some(*value as u64),
+        _ => None,
+    }
+}
+
+pub async fn quota_limit(data: &AppState, owner: &str) -> Result<Option<u64>, CustomError> {
+    let id = ObjectId::parse_str(owner).map_err(|_| CustomError::JWTError)?;
+    let user = data
+        .user_collection
+        .find_one(doc! {"_id": id}, None)
+        .await?
+        .ok_or(CustomError::JWTError)?;
+    let limit = as_u64(user.get("quota")).unwrap_or(data.opt.default_quota);
+    Ok(Some(limit).filter(|limit| *limit > 0))
+}
+
+/// Gives an account a limit of its own, or with `None` puts it back on the
+/// default. A limit of `0` means no limit.
+pub async fn set_quota(
+    data: &AppState,
+    owner: &str,
+    quota: Option<u64>,
+) -> Result<(), CustomError> {
+    let id = ObjectId::parse_str(owner).map_err(|_| CustomError::UnknownAccount)?;
+    let update = match quota {
+        Some(quota) => doc! {"$set": {"quota": quota as i64}},
+        None => doc! {"$unset": {"quota": ""}},
+    };
+    let result = data
+        .user_collection
+        .update_one(doc! {"_id": id}, update, None)
+        .await?;
+    if result.matched_count == 0 {
+        return Err(CustomError::UnknownAccount);
+    }
+    Ok(())
+}
+
+pub async fn usage(data: &AppState, owner: &str) -> Result<Usage, CustomError> {
+    let groups: Vec<Document> = data
+        .file_collection
+        .clone_with_type::<Document>()
+        .aggregate(
+            vec![
+                doc! {"$match": {"owner": owner, "kind": "file"}},
+                doc! {"$group": {
+                    "_id": {"$arrayElemAt": [{"$split": ["$mime", "/"]}, 0]},
+                    "bytes": {"$sum": "$size"},
+                    "files": {"$sum": 1},
+                }},
+                doc! {"$sort": {"bytes": -1}},
+            ],
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    let breakdown: Vec<TypeUsage> = groups
+        .iter()
+        .map(|group| TypeUsage {
+            file_type: group.get_str("_id").unwrap_or_default().to_owned(),
+            bytes: as_u64(group.get("bytes")).unwrap_or_default(),
+            files: as_u64(group.get("files")).unwrap_or_default(),
+        })
+        .collect();
+    let versions = data
+        .version_collection
+        .clone_with_type::<Document>()
+        .aggregate(
+            vec![
+                doc! {"$match": {"owner": owner}},
+                doc! {"$group": {"_id": null, "bytes": {"$sum": "$size"}}},
+            ],
+            None,
+        )
+        .await?
+        .try_next()
+        .await?
+        .and_then(|total| as_u64(total.get("bytes")))
+        .unwrap_or_default();
+    Ok(Usage {
+        used: breakdown.iter().map(|usage| usage.bytes).sum::<u64>() + versions,
+        limit: quota_limit(data, owner).await?,
+        versions,
+        breakdown,
+    })
+}
+
+pub async fn remaining_quota(data: &AppState, owner: &str) -> Result<Option<u64>, CustomError> {
+    let usage = usage(data, owner).await?;
+    Ok(usage.limit.map(|limit| limit.saturating_sub(usage.used)))
+}
+
+pub async fn check_quota(data: &AppState, owner: &str, additional: i64) -> Result<(), CustomError> {
+    if additional <= 0 {
+        return Ok(());
+    }
+    match remaining_quota(data, owner).await? {
+        Some(remaining) if additional as u64 > remaining => Err(CustomError::QuotaExceeded),
+        _ => Ok(()),
+    }
+}
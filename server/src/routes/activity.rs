@@ -0,0 +1,57 @@
+use actix_web::{get, http::StatusCode, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    activity::{recent_entries, Action},
+    metadata::FileMetadata,
+    middleware::AuthenticationExtractor,
+    stars::starred_among,
+    utils::CustomError,
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct RecentQuery {
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentEntry {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    action: Action,
+    at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentListing {
+    items: Vec<RecentEntry>,
+}
+
+#[get("/recent")]
+pub async fn get_recent(
+    query: web::Query<RecentQuery>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let recent = recent_entries(&data, &auth, limit).await?;
+    let ids = recent.iter().map(|(_, record)| record.id).collect();
+    let starred = starred_among(&data, &auth, ids).await?;
+    let items = recent
+        .iter()
+        .map(|(event, record)| RecentEntry {
+            metadata: FileMetadata::from(record).with_starred(starred.contains(&record.id)),
+            action: event.action,
+            at: event.at.to_chrono(),
+        })
+        .collect();
+    Ok(HttpResponse::build(StatusCode::OK).json(RecentListing { items }))
+}
@@ -0,0 +1,44 @@
+use actix_web::{get, http::StatusCode, post, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    archives::{extract_archive, list_archive},
+    grants::{authorize_file, authorize_folder, Access},
+    middleware::AuthenticationExtractor,
+    utils::{ConflictPolicy, CustomError},
+    AppState,
+};
+
+#[get("/files/{id}/archive")]
+pub async fn get_archive_contents(
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Read).await?;
+    let listing = list_archive(&data, &auth, &record).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(listing))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExtractOptions {
+    destination: Option<String>,
+    conflict: Option<ConflictPolicy>,
+}
+
+#[post("/files/{id}/extract")]
+pub async fn extract_file(
+    path: web::Path<String>,
+    body: web::Json<ExtractOptions>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Read).await?;
+    let destination =
+        authorize_folder(&data, &auth, body.destination.as_deref(), Access::Write).await?;
+    // Merging into folders that already exist is the common case, so clashing
+    // files get a numbered name unless the caller asks otherwise.
+    let conflict = body.conflict.unwrap_or(ConflictPolicy::Rename);
+    let summary = extract_archive(&data, &auth, &record, &destination, conflict).await?;
+    Ok(HttpResponse::build(StatusCode::CREATED).json(summary))
+}
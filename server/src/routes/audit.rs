@@ -0,0 +1,65 @@
+use actix_web::{get, http::StatusCode, web, HttpResponse};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{list_events, AuditEvent, AuditMetadata},
+    middleware::AuthenticationExtractor,
+    utils::CustomError,
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    user: Option<String>,
+    event: Option<AuditEvent>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    items: Vec<AuditMetadata>,
+    next_cursor: Option<String>,
+}
+
+/// Users see the events they made or that involve their account. Admins see
+/// everyone's, or a single account's with `user`.
+#[get("/audit")]
+pub async fn get_audit_log(
+    query: web::Query<AuditQuery>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let user = match (data.config.is_admin(&auth), query.user.as_deref()) {
+        (true, user) => user,
+        (false, None) => Some(auth.as_str()),
+        (false, Some(user)) if user == *auth => Some(user),
+        (false, Some(_)) => return Err(CustomError::Forbidden),
+    };
+    let before = query
+        .cursor
+        .as_deref()
+        .map(ObjectId::parse_str)
+        .transpose()
+        .map_err(|_| CustomError::InvalidCursor)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut records = list_events(&data, user, query.event, before, limit + 1).await?;
+    let next_cursor = if records.len() > limit as usize {
+        records.truncate(limit as usize);
+        records.last().map(|record| record.id.to_hex())
+    } else {
+        None
+    };
+    Ok(HttpResponse::build(StatusCode::OK).json(AuditPage {
+        items: records.iter().map(AuditMetadata::from).collect(),
+        next_cursor,
+    }))
+}
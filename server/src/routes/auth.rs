@@ -1,4 +1,4 @@
-use actix_web::{http::StatusCode, post, web, HttpResponse};
+use actix_web::{http::StatusCode, post, web, HttpRequest, HttpResponse};
 use bcrypt::{hash, verify, DEFAULT_COST};
 use chrono::Utc;
 use jsonwebtoken::{encode, EncodingKey, Header};
@@ -8,9 +8,12 @@ use mongodb::{
     Client,
 };
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 
-use crate::{utils::CustomError, AppState};
+use crate::{
+    audit::{record_event, AuditEvent, AuditRecord},
+    utils::CustomError,
+    AppState,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -58,6 +61,7 @@ pub fn generate_token(id: String, secret_key: String, expiry_seconds: i64) -> St
 
 #[post("/login")]
 pub async fn login(
+    req: HttpRequest,
     body: web::Json<LoginInfo>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, CustomError> {
@@ -67,17 +71,13 @@ pub async fn login(
         .await
     {
         let user_password = user.get_str("password").unwrap();
+        let user_id = user.get_object_id("_id").unwrap().to_string();
         if verify(&body.password, user_password).unwrap() {
-            let user_id = user.get_object_id("_id").unwrap().to_string();
-
-            if !fs::try_exists(format!("./files/{}", user_id))
-                .await
-                .unwrap()
-            {
-                fs::create_dir(format!("./files/{}", user_id))
-                    .await
-                    .unwrap();
-            }
+            record_event(
+                &data,
+                AuditRecord::new(&data, &req, AuditEvent::LoginSucceeded, Some(&user_id)),
+            )
+            .await;
 
             let data = ReturnedData {
                 id: user_id.clone(),
@@ -92,15 +92,23 @@ pub async fn login(
 
             Ok(HttpResponse::build(StatusCode::OK).json(data))
         } else {
+            let event = AuditRecord::new(&data, &req, AuditEvent::LoginFailed, None)
+                .with_owner(Some(&user_id))
+                .with_detail(&body.email);
+            record_event(&data, event).await;
             Err(CustomError::LoginError)
         }
     } else {
+        let event =
+            AuditRecord::new(&data, &req, AuditEvent::LoginFailed, None).with_detail(&body.email);
+        record_event(&data, event).await;
         Err(CustomError::LoginError)
     }
 }
 
 #[post("/signup")]
 pub async fn signup(
+    req: HttpRequest,
     data: web::Data<AppState>,
     body: web::Json<AccountDetails>,
 ) -> Result<HttpResponse, CustomError> {
@@ -126,9 +134,9 @@ pub async fn signup(
 
     let new_entry = users.insert_one(new_user, None).await.unwrap();
     let entry_id = new_entry.inserted_id.as_object_id().unwrap().to_string();
-    fs::create_dir(format!("./files/{}", entry_id))
-        .await
-        .unwrap();
+    let event =
+        AuditRecord::new(&data, &req, AuditEvent::Signup, Some(&entry_id)).with_detail(&body.email);
+    record_event(&data, event).await;
 
     let data = ReturnedData {
         id: entry_id.clone(),
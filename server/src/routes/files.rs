@@ -1,78 +1,753 @@
-use actix_web::{get, http::StatusCode, post, web, HttpResponse};
-use serde::Deserialize;
-use tokio::fs::{self, DirEntry};
-
-use crate::{middleware::AuthenticationExtractor, utils::CustomError};
-
-#[get("/count")]
-pub async fn get_file_count(auth: AuthenticationExtractor) -> Result<HttpResponse, CustomError> {
-    let id = auth.clone();
-    let dir = fs::read_dir(format!("./files/{}", id)).await;
-    return match dir {
-        Ok(mut entries) => {
-            let mut count = 0;
-            while let Some(_entry) = entries.next_entry().await.unwrap() {
-                count += 1;
-            }
+use std::collections::{HashMap, HashSet};
+
+use actix_multipart::Multipart;
+use actix_web::{
+    get,
+    http::{
+        header::{self, DispositionType, EntityTag, Header, IfNoneMatch},
+        StatusCode,
+    },
+    post, put, route, web, HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    activity::{record_activity, Action},
+    audit::{record_event, AuditEvent, AuditRecord},
+    blobs::dedup_stats,
+    download::{file_disposition, Download},
+    grants::{authorize, authorize_file, authorize_folder, Access},
+    metadata::{
+        children_filter, descendants, is_within, listing_options, remove_entry, store_content,
+        type_filter, Content, EntryKind, FileMetadata, FileRecord, Target,
+    },
+    middleware::AuthenticationExtractor,
+    quota::{remaining_quota, set_quota, usage},
+    staging::StagedFile,
+    stars::starred_among,
+    tags::tag_filter,
+    text_index::index_record,
+    thumbnails::{thumbnail, ThumbnailSize},
+    trash::trash_entry,
+    utils::{numbered_name, validate_file_name, ConflictPolicy, CustomError},
+    versions::keeps_versions,
+    zip_stream::{zip_stream, ZipEntry},
+    AppState,
+};
 
-            return Ok(HttpResponse::build(StatusCode::OK).body(count.to_string()));
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortKey {
+    fn field(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Type => "mime",
         }
-        Err(_) => Err(CustomError::MissingPath),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListFiles {
+    folder: Option<String>,
+    limit: Option<u32>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    #[serde(rename = "type")]
+    types: Option<String>,
+    tag: Option<String>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilePage {
+    items: Vec<FileMetadata>,
+    next_cursor: Option<String>,
+    total: u64,
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+fn encode_cursor(record: &FileRecord, sort: SortKey) -> Result<String, CustomError> {
+    let fields = mongodb::bson::to_document(record).map_err(|_| CustomError::InternalError)?;
+    let cursor = doc! {
+        "kind": fields.get("kind").cloned().unwrap_or(Bson::Null),
+        "value": fields.get(sort.field()).cloned().unwrap_or(Bson::Null),
+        "id": record.id,
     };
+    let bytes = mongodb::bson::to_vec(&cursor).map_err(|_| CustomError::InternalError)?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn cursor_filter(cursor: &str, sort: SortKey, order: SortOrder) -> Result<Document, CustomError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| CustomError::InvalidCursor)?;
+    let cursor: Document =
+        mongodb::bson::from_slice(&bytes).map_err(|_| CustomError::InvalidCursor)?;
+    let (kind, value, id) = match (cursor.get("kind"), cursor.get("value"), cursor.get("id")) {
+        (Some(kind), Some(value), Some(id)) => (kind.clone(), value.clone(), id.clone()),
+        _ => return Err(CustomError::InvalidCursor),
+    };
+    let comparison = match order {
+        SortOrder::Asc => "$gt",
+        SortOrder::Desc => "$lt",
+    };
+    Ok(doc! {"$or": [
+        {"kind": {"$lt": kind.clone()}},
+        {"kind": kind.clone(), sort.field(): {comparison: value.clone()}},
+        {"kind": kind, sort.field(): value, "_id": {comparison: id}},
+    ]})
+}
+
+#[get("/files")]
+pub async fn list_files(
+    query: web::Query<ListFiles>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let files = &data.file_collection;
+    let location = authorize_folder(&data, &auth, query.folder.as_deref(), Access::Read).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut filter = children_filter(&location.owner, location.folder);
+    if let Some(types) = query.types.as_deref().and_then(type_filter) {
+        filter.extend(types);
+    }
+    if let Some(tags) = query.tag.as_deref().map(tag_filter).transpose()?.flatten() {
+        filter.extend(tags);
+    }
+    let total = files.count_documents(filter.clone(), None).await?;
+
+    let page_filter = match &query.cursor {
+        Some(cursor) => doc! {"$and": [filter, cursor_filter(cursor, query.sort, query.order)?]},
+        None => filter,
+    };
+    let direction = match query.order {
+        SortOrder::Asc => 1,
+        SortOrder::Desc => -1,
+    };
+    let mut options = listing_options();
+    options.sort = Some(doc! {"kind": -1, query.sort.field(): direction, "_id": direction});
+    options.limit = Some(limit as i64 + 1);
+
+    let mut records: Vec<FileRecord> = files
+        .find(page_filter, options)
+        .await?
+        .try_collect()
+        .await?;
+    let next_cursor = if records.len() > limit as usize {
+        records.truncate(limit as usize);
+        records
+            .last()
+            .map(|record| encode_cursor(record, query.sort))
+            .transpose()?
+    } else {
+        None
+    };
+
+    let ids = records.iter().map(|record| record.id).collect();
+    let starred = starred_among(&data, &auth, ids).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(FilePage {
+        items: records
+            .iter()
+            .map(|record| FileMetadata::from(record).with_starred(starred.contains(&record.id)))
+            .collect(),
+        next_cursor,
+        total,
+    }))
+}
+
+#[get("/files/{id}")]
+pub async fn get_file_metadata(
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Read).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct GetFiles {
-    indices: Vec<u32>,
-    count: u32,
+pub struct UploadOptions {
+    folder: Option<String>,
+    #[serde(default)]
+    conflict: ConflictPolicy,
 }
 
-#[post("/indices")]
-pub async fn get_files_indices(
-    body: web::Json<GetFiles>,
+#[post("/upload")]
+pub async fn upload_files(
+    req: HttpRequest,
+    mut payload: Multipart,
+    options: web::Query<UploadOptions>,
     auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
 ) -> Result<HttpResponse, CustomError> {
-    let id = auth.clone();
-    let dir = fs::read_dir(format!("./files/{}", id)).await;
-    if body.count > 0 {
-        match dir {
-            Ok(mut entries) => {
-                let mut indices_clone = body.indices.clone();
-
-                let sorted_indices = indices_clone.as_mut_slice();
-                sorted_indices.sort();
-                let highest_index = *sorted_indices.last().unwrap();
-                let mut files: Vec<DirEntry> = vec![];
-                if highest_index == 0 {
-                    files.push(entries.next_entry().await.unwrap().unwrap());
-                } else {
-                    for _ in 0..highest_index as usize {
-                        files.push(entries.next_entry().await.unwrap().unwrap());
-                    }
+    let files = &data.file_collection;
+    let destination =
+        authorize_folder(&data, &auth, options.folder.as_deref(), Access::Write).await?;
+    let staging_dir = data.opt.staging_dir(&auth);
+    fs::create_dir_all(&staging_dir).await?;
+
+    let mut uploaded: Vec<FileMetadata> = vec![];
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|_| CustomError::MissingBody)?
+    {
+        let file_name = match field.content_disposition().get_filename() {
+            Some(name) => validate_file_name(name)?.to_owned(),
+            None => continue,
+        };
+        let freed = match options
+            .conflict
+            .resolve(files, &destination.owner, destination.folder, &file_name)
+            .await?
+        {
+            Target::Replace(existing) if !keeps_versions(&data) => existing.size as u64,
+            _ => 0,
+        };
+        let allowance = remaining_quota(&data, &destination.owner)
+            .await?
+            .map(|remaining| remaining + freed);
+
+        let temp_path = staging_dir.join(uuid::Uuid::new_v4().to_string());
+        let mut file = StagedFile::create(data.staging_key.as_ref(), &temp_path).await?;
+        let mut size: u64 = 0;
+        let write_result: Result<(), CustomError> = async {
+            while let Some(chunk) = field
+                .try_next()
+                .await
+                .map_err(|_| CustomError::MissingBody)?
+            {
+                size += chunk.len() as u64;
+                if size > data.opt.max_upload_size {
+                    return Err(CustomError::PayloadTooLarge);
+                }
+                if allowance.is_some_and(|allowance| size > allowance) {
+                    return Err(CustomError::QuotaExceeded);
                 }
-                let filtered_files: Vec<(String, String)> = files
-                    .iter()
-                    .enumerate()
-                    .filter(|(i, _)| indices_clone.contains(&(*i as u32)))
-                    .map(|(_, entry)| {
-                        let entry_name = entry.file_name().to_string_lossy().to_string();
-                        let entry_type = {
-                            let path = entry.path();
-                            if let Some(extension) = path.extension() {
-                                extension.to_string_lossy().to_string()
-                            } else {
-                                String::new()
-                            }
-                        };
-                        (entry_name, entry_type)
-                    })
-                    .collect();
-
-                return Ok(HttpResponse::build(StatusCode::OK).json(filtered_files));
+                file.write_all(&chunk).await?;
             }
-            Err(_) => Err(CustomError::MissingPath),
+            file.flush().await?;
+            Ok(())
+        }
+        .await;
+        drop(file);
+
+        if let Err(e) = write_result {
+            fs::remove_file(&temp_path).await.ok();
+            return Err(e);
+        }
+        let record = store_content(
+            &data,
+            &destination.owner,
+            destination.folder,
+            &file_name,
+            options.conflict,
+            Content::Staged(&temp_path),
+        )
+        .await?;
+        record_activity(&data, &auth, &record, Action::Uploaded).await;
+        let event = AuditRecord::new(&data, &req, AuditEvent::Upload, Some(&auth));
+        record_event(&data, event.with_entry(&record)).await;
+        uploaded.push(FileMetadata::from(&record));
+    }
+
+    if uploaded.is_empty() {
+        return Err(CustomError::MissingBody);
+    }
+
+    Ok(HttpResponse::build(StatusCode::CREATED).json(uploaded))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadOptions {
+    #[serde(default)]
+    pub attachment: bool,
+}
+
+#[route("/download/{id}", method = "GET", method = "HEAD")]
+pub async fn download_file(
+    req: HttpRequest,
+    path: web::Path<String>,
+    options: web::Query<DownloadOptions>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Read).await?;
+    let download = Download::new(data.storage.clone(), &record);
+    if download.starts_transfer(&req) {
+        record_activity(&data, &auth, &record, Action::Opened).await;
+        let event = AuditRecord::new(&data, &req, AuditEvent::Download, Some(&auth));
+        record_event(&data, event.with_entry(&record)).await;
+    }
+    Ok(download.into_response(&req, options.attachment))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadArchive {
+    ids: Vec<String>,
+    name: Option<String>,
+}
+
+/// Each selected entry lands at the top of the archive under its own name,
+/// with folders bringing their contents along.
+async fn archive_entries(
+    data: &AppState,
+    user: &str,
+    ids: &[String],
+) -> Result<Vec<ZipEntry>, CustomError> {
+    let mut entries = vec![];
+    let mut used = HashSet::new();
+    for id in ids {
+        let record = authorize(data, user, id, Access::Read).await?;
+        let mut name = record.name.clone();
+        for n in 1.. {
+            if used.insert(name.to_lowercase()) {
+                break;
+            }
+            name = numbered_name(&record.name, n);
+        }
+        if record.kind == EntryKind::File {
+            entries.push(ZipEntry { path: name, record });
+            continue;
+        }
+
+        let mut paths = HashMap::from([(record.id, format!("{}/", name))]);
+        let contents = descendants(&data.file_collection, &record).await?;
+        entries.push(ZipEntry {
+            path: format!("{}/", name),
+            record,
+        });
+        for child in contents {
+            let parent = child
+                .parent
+                .and_then(|parent| paths.get(&parent))
+                .cloned()
+                .unwrap_or_default();
+            let path = match child.kind {
+                EntryKind::Folder => {
+                    let path = format!("{}{}/", parent, child.name);
+                    paths.insert(child.id, path.clone());
+                    path
+                }
+                EntryKind::File => format!("{}{}", parent, child.name),
+            };
+            entries.push(ZipEntry {
+                path,
+                record: child,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+#[post("/zip")]
+pub async fn download_archive(
+    req: HttpRequest,
+    body: web::Json<DownloadArchive>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    if body.ids.is_empty() {
+        return Err(CustomError::MissingBody);
+    }
+    let entries = archive_entries(&data, &auth, &body.ids).await?;
+    let name = match (&body.name, entries.as_slice()) {
+        (Some(name), _) => validate_file_name(name)?.to_owned(),
+        (None, [folder, ..]) if body.ids.len() == 1 && folder.record.kind == EntryKind::Folder => {
+            folder.record.name.clone()
+        }
+        (None, _) => "download".to_owned(),
+    };
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.record.kind == EntryKind::File)
+    {
+        let event = AuditRecord::new(&data, &req, AuditEvent::Download, Some(&auth))
+            .with_entry(&entry.record)
+            .with_detail(format!("{}.zip", name.trim_end_matches(".zip")));
+        record_event(&data, event).await;
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "application/zip"))
+        .insert_header(file_disposition(
+            DispositionType::Attachment,
+            &format!("{}.zip", name.trim_end_matches(".zip")),
+        ))
+        .streaming(zip_stream(data.storage.clone(), entries)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailOptions {
+    #[serde(default)]
+    size: ThumbnailSize,
+}
+
+#[get("/thumbnail/{id}")]
+pub async fn get_thumbnail(
+    req: HttpRequest,
+    path: web::Path<String>,
+    options: web::Query<ThumbnailOptions>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Read).await?;
+    let etag = EntityTag::new_strong(format!(
+        "{}-{}",
+        record.id.to_hex(),
+        record.modified.timestamp_millis()
+    ));
+    if let Ok(IfNoneMatch::Items(items)) = IfNoneMatch::parse(&req) {
+        if items.iter().any(|item| item.weak_eq(&etag)) {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(header::ETag(etag))
+                .finish());
         }
+    }
+    let thumbnail = thumbnail(data.storage.as_ref(), &record, options.size).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ContentType::png())
+        .insert_header(header::CacheControl(vec![
+            header::CacheDirective::Private,
+            header::CacheDirective::MaxAge(86400),
+        ]))
+        .insert_header(header::ETag(etag))
+        .body(thumbnail))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteFile {
+    id: String,
+    #[serde(default)]
+    permanent: bool,
+}
+
+#[post("/delete")]
+pub async fn delete_file(
+    req: HttpRequest,
+    body: web::Json<DeleteFile>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &body.id, Access::Write).await?;
+    let event = if body.permanent {
+        remove_entry(&data, &record).await?;
+        AuditEvent::Delete
     } else {
-        Err(CustomError::MissingPath)
+        trash_entry(&data, &record).await?;
+        AuditEvent::Trash
+    };
+    let event = AuditRecord::new(&data, &req, event, Some(&auth));
+    record_event(&data, event.with_entry(&record)).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn relocate(
+    data: &AppState,
+    mut record: FileRecord,
+    parent: Option<ObjectId>,
+    name: &str,
+    conflict: ConflictPolicy,
+) -> Result<FileRecord, CustomError> {
+    let files = &data.file_collection;
+    if record.parent == parent && record.name == name {
+        return Ok(record);
+    }
+    let previous_name = record.name.clone();
+    match conflict.resolve(files, &record.owner, parent, name).await? {
+        Target::Create(name) => record.name = name,
+        Target::Replace(existing) => {
+            if record.kind == EntryKind::Folder {
+                return Err(CustomError::Conflict);
+            }
+            remove_entry(data, &existing).await?;
+            record.name = existing.name;
+        }
+    }
+    record.parent = parent;
+    record.modified = DateTime::now();
+    files
+        .update_one(
+            doc! {"_id": record.id},
+            doc! {"$set": {"parent": record.parent, "name": &record.name, "modified": record.modified}},
+            None,
+        )
+        .await?;
+    if record.name != previous_name && record.kind == EntryKind::File {
+        index_record(data, &record).await;
+    }
+    Ok(record)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameFile {
+    id: String,
+    new_name: String,
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+#[post("/rename")]
+pub async fn rename_file(
+    body: web::Json<RenameFile>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &body.id, Access::Write).await?;
+    let new_name = validate_file_name(&body.new_name)?;
+    let parent = record.parent;
+    let record = relocate(&data, record, parent, new_name, body.conflict).await?;
+    record_activity(&data, &auth, &record, Action::Modified).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferFile {
+    id: String,
+    destination: Option<String>,
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+#[post("/move")]
+pub async fn move_file(
+    body: web::Json<TransferFile>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let files = &data.file_collection;
+    let record = authorize(&data, &auth, &body.id, Access::Write).await?;
+    let destination =
+        authorize_folder(&data, &auth, body.destination.as_deref(), Access::Write).await?;
+    // Entries can't change hands by moving them, only by copying.
+    if destination.owner != record.owner {
+        return Err(CustomError::InvalidDestination);
+    }
+    if record.kind == EntryKind::Folder
+        && is_within(files, &record.owner, destination.folder, record.id).await?
+    {
+        return Err(CustomError::InvalidDestination);
+    }
+    let name = record.name.clone();
+    let record = relocate(&data, record, destination.folder, &name, body.conflict).await?;
+    record_activity(&data, &auth, &record, Action::Modified).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
+}
+
+#[post("/copy")]
+pub async fn copy_file(
+    body: web::Json<TransferFile>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let source = authorize_file(&data, &auth, &body.id, Access::Read).await?;
+    let destination =
+        authorize_folder(&data, &auth, body.destination.as_deref(), Access::Write).await?;
+    let record = store_content(
+        &data,
+        &destination.owner,
+        destination.folder,
+        &source.name,
+        body.conflict,
+        Content::Copy(&source),
+    )
+    .await?;
+    record_activity(&data, &auth, &record, Action::Uploaded).await;
+    Ok(HttpResponse::build(StatusCode::CREATED).json(FileMetadata::from(&record)))
+}
+
+#[get("/usage")]
+pub async fn get_usage(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::build(StatusCode::OK).json(usage(&data, &auth).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetQuota {
+    quota: Option<u64>,
+}
+
+/// Sets another account's storage limit, which only admins can do. Returns
+/// the account's usage under the new limit.
+#[put("/users/{id}/quota")]
+pub async fn set_user_quota(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<SetQuota>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    if !data.config.is_admin(&auth) {
+        return Err(CustomError::Forbidden);
+    }
+    set_quota(&data, &path, body.quota).await?;
+    let detail = body
+        .quota
+        .map_or_else(|| String::from("default"), |quota| quota.to_string());
+    let event = AuditRecord::new(&data, &req, AuditEvent::QuotaChanged, Some(&auth))
+        .with_owner(Some(&path))
+        .with_detail(detail);
+    record_event(&data, event).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(usage(&data, &path).await?))
+}
+
+/// Storage-wide figures, so only admins can see them.
+#[get("/dedup")]
+pub async fn get_dedup_stats(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    if !data.config.is_admin(&auth) {
+        return Err(CustomError::Forbidden);
+    }
+    let stats = dedup_stats(&data.file_collection, &data.blob_collection).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(stats))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFolder {
+    parent: Option<String>,
+    name: String,
+}
+
+#[post("/folders")]
+pub async fn create_folder(
+    body: web::Json<CreateFolder>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let destination = authorize_folder(&data, &auth, body.parent.as_deref(), Access::Write).await?;
+    let name = validate_file_name(&body.name)?;
+    let record = FileRecord::new(
+        &destination.owner,
+        destination.folder,
+        name,
+        EntryKind::Folder,
+    );
+    data.file_collection.insert_one(&record, None).await?;
+    Ok(HttpResponse::build(StatusCode::CREATED).json(FileMetadata::from(&record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteFolder {
+    id: String,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    permanent: bool,
+}
+
+#[post("/folders/delete")]
+pub async fn delete_folder(
+    req: HttpRequest,
+    body: web::Json<DeleteFolder>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let files = &data.file_collection;
+    let folder = authorize(&data, &auth, &body.id, Access::Write).await?;
+    if folder.kind != EntryKind::Folder {
+        return Err(CustomError::MissingPath);
+    }
+    let contents = descendants(files, &folder).await?;
+    if !contents.is_empty() && !body.recursive {
+        return Err(CustomError::FolderNotEmpty);
+    }
+    if !body.permanent {
+        trash_entry(&data, &folder).await?;
+        let event = AuditRecord::new(&data, &req, AuditEvent::Trash, Some(&auth));
+        record_event(&data, event.with_entry(&folder)).await;
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    for record in contents.iter().rev() {
+        remove_entry(&data, record).await?;
+    }
+    remove_entry(&data, &folder).await?;
+    let event = AuditRecord::new(&data, &req, AuditEvent::Delete, Some(&auth))
+        .with_entry(&folder)
+        .with_detail(format!("{} entries inside", contents.len()));
+    record_event(&data, event).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> FileRecord {
+        let mut record = FileRecord::new("user", None, "Report.pdf", EntryKind::File);
+        record.size = 1234;
+        record
+    }
+
+    #[test]
+    fn cursors_round_trip_the_sort_position() {
+        let record = record();
+        let cases = [
+            (SortKey::Name, Bson::from("Report.pdf")),
+            (SortKey::Size, Bson::Int64(1234)),
+            (SortKey::Modified, Bson::DateTime(record.modified)),
+            (SortKey::Type, Bson::from("application/pdf")),
+        ];
+        for (sort, value) in cases {
+            let cursor = encode_cursor(&record, sort).unwrap();
+            for (order, comparison) in [(SortOrder::Asc, "$gt"), (SortOrder::Desc, "$lt")] {
+                let filter = cursor_filter(&cursor, sort, order).unwrap();
+                let field = sort.field();
+                let expected = doc! {"$or": [
+                    {"kind": {"$lt": "file"}},
+                    {"kind": "file", field: {comparison: value.clone()}},
+                    {"kind": "file", field: value.clone(), "_id": {comparison: record.id}},
+                ]};
+                assert_eq!(filter, expected, "{:?} {:?}", sort, order);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        let incomplete = URL_SAFE_NO_PAD
+            .encode(mongodb::bson::to_vec(&doc! {"kind": "file", "value": "a"}).unwrap());
+        for cursor in ["", "not base64!", "AAAA", incomplete.as_str()] {
+            assert!(
+                matches!(
+                    cursor_filter(cursor, SortKey::Name, SortOrder::Asc),
+                    Err(CustomError::InvalidCursor)
+                ),
+                "{:?}",
+                cursor
+            );
+        }
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{record_event, AuditEvent, AuditRecord},
+    grants::{list_grants, shared_with, Access, GrantMetadata, GrantRecord},
+    metadata::{find_entry, FileMetadata},
+    middleware::AuthenticationExtractor,
+    utils::CustomError,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGrant {
+    entry: String,
+    email: String,
+    access: Access,
+}
+
+#[post("/grants")]
+pub async fn create_grant(
+    req: HttpRequest,
+    body: web::Json<CreateGrant>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = find_entry(&data.file_collection, &auth, &body.entry).await?;
+    let email = body.email.trim();
+    let grantee = data
+        .user_collection
+        .find_one(doc! {"email": email}, None)
+        .await?
+        .and_then(|user| user.get_object_id("_id").ok())
+        .ok_or(CustomError::UnknownAccount)?
+        .to_string();
+    if grantee == *auth {
+        return Err(CustomError::InvalidGrant);
+    }
+
+    // Granting again to the same account just changes its access level.
+    let access = mongodb::bson::to_bson(&body.access).map_err(|_| CustomError::InternalError)?;
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let grant = data
+        .grant_collection
+        .find_one_and_update(
+            doc! {"entry": record.id, "grantee": &grantee},
+            doc! {
+                "$set": {"access": access},
+                "$setOnInsert": {
+                    "_id": ObjectId::new(),
+                    "owner": &*auth,
+                    "email": email,
+                    "created": BsonDateTime::now(),
+                },
+            },
+            options,
+        )
+        .await?
+        .ok_or(CustomError::InternalError)?;
+    let event = AuditRecord::new(&data, &req, AuditEvent::GrantChanged, Some(&auth))
+        .with_entry(&record)
+        .with_detail(format!("{} ({:?})", grant.email, grant.access));
+    record_event(&data, event).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(GrantMetadata::from(&grant)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListGrants {
+    entry: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrantListing {
+    items: Vec<GrantMetadata>,
+}
+
+#[get("/grants")]
+pub async fn get_grants(
+    query: web::Query<ListGrants>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let entry = match &query.entry {
+        Some(id) => Some(find_entry(&data.file_collection, &auth, id).await?.id),
+        None => None,
+    };
+    let grants = list_grants(&data, &auth, entry).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(GrantListing {
+        items: grants.iter().map(GrantMetadata::from).collect(),
+    }))
+}
+
+#[post("/grants/{id}/revoke")]
+pub async fn revoke_grant(
+    req: HttpRequest,
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let id = ObjectId::parse_str(path.as_str()).map_err(|_| CustomError::MissingPath)?;
+    let grant = data
+        .grant_collection
+        .find_one_and_delete(doc! {"_id": id, "owner": &*auth}, None)
+        .await?
+        .ok_or(CustomError::MissingPath)?;
+    let mut event = AuditRecord::new(&data, &req, AuditEvent::GrantRevoked, Some(&auth))
+        .with_detail(grant.email.clone());
+    event.entry = Some(grant.entry);
+    record_event(&data, event).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedEntry {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    access: Access,
+    shared_by: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedListing {
+    items: Vec<SharedEntry>,
+}
+
+async fn owner_email(
+    data: &AppState,
+    grant: &GrantRecord,
+    cache: &mut HashMap<String, String>,
+) -> Result<String, CustomError> {
+    if let Some(email) = cache.get(&grant.owner) {
+        return Ok(email.clone());
+    }
+    let id = ObjectId::parse_str(&grant.owner).map_err(|_| CustomError::InternalError)?;
+    let email = data
+        .user_collection
+        .find_one(doc! {"_id": id}, None)
+        .await?
+        .and_then(|user| user.get_str("email").ok().map(str::to_owned))
+        .unwrap_or_default();
+    cache.insert(grant.owner.clone(), email.clone());
+    Ok(email)
+}
+
+#[get("/shared")]
+pub async fn get_shared_with_me(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let mut emails = HashMap::new();
+    let mut items = vec![];
+    for (grant, record) in shared_with(&data, &auth).await? {
+        items.push(SharedEntry {
+            metadata: FileMetadata::from(&record),
+            access: grant.access,
+            shared_by: owner_email(&data, &grant, &mut emails).await?,
+        });
+    }
+    Ok(HttpResponse::build(StatusCode::OK).json(SharedListing { items }))
+}
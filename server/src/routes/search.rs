@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+
+use actix_web::{get, http::StatusCode, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime as BsonDateTime},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::{folder_path, regex_escape, type_filter, FileMetadata, FileRecord},
+    middleware::AuthenticationExtractor,
+    stars::starred_among,
+    tags::tag_filter,
+    text_index::{read_text, TextHit},
+    utils::CustomError,
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const MAX_QUERY_LENGTH: usize = 256;
+const MAX_CANDIDATES: i64 = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(rename = "type")]
+    types: Option<String>,
+    tag: Option<String>,
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+    modified_after: Option<DateTime<Utc>>,
+    modified_before: Option<DateTime<Utc>>,
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+}
+
+impl SearchQuery {
+    fn has_filters(&self) -> bool {
+        self.types.is_some()
+            || self.tag.is_some()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.modified_after.is_some()
+            || self.modified_before.is_some()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    path: String,
+    score: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    items: Vec<SearchResult>,
+    total: u64,
+    next_offset: Option<u32>,
+    /// Whether only the most recently modified candidates were scored, so
+    /// `total` undercounts and some matches are missing.
+    truncated: bool,
+    /// When truncated, an estimate of how many files match in all.
+    approximate_total: Option<u64>,
+}
+
+fn is_boundary(name: &[char], index: usize) -> bool {
+    index == 0
+        || !name[index - 1].is_alphanumeric()
+        || (name[index - 1].is_lowercase() && name[index].is_uppercase())
+}
+
+/// The longest term that's still matched with typos.
+const MAX_TYPO_TERM: usize = 64;
+
+/// How many typos a term of `length` characters may contain and still match.
+/// Short terms get none, since almost anything is a typo or two away from them,
+/// and neither do very long ones, to bound the cost of comparing them.
+fn typo_budget(length: usize) -> usize {
+    match length {
+        0..=4 => 0,
+        5..=8 => 1,
+        9..=MAX_TYPO_TERM => 2,
+        _ => 0,
+    }
+}
+
+/// The fewest typos (a character inserted, deleted, replaced or swapped with
+/// its neighbour) it takes for `term` to appear somewhere in `name`, or `None`
+/// when that's more than `budget`.
+fn typo_distance(name: &[char], term: &[char], budget: usize) -> Option<usize> {
+    // Only the last three rows of the table are ever looked at.
+    let mut before = vec![0; name.len() + 1];
+    let mut previous = vec![0; name.len() + 1];
+    let mut current = vec![0; name.len() + 1];
+    for i in 1..=term.len() {
+        current[0] = i;
+        for j in 1..=name.len() {
+            let replaced = previous[j - 1] + usize::from(term[i - 1] != name[j - 1]);
+            let mut best = replaced.min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && term[i - 1] == name[j - 2] && term[i - 2] == name[j - 1] {
+                best = best.min(before[j - 2] + 1);
+            }
+            current[j] = best;
+        }
+        // No later row can do better than the two before it, so once both are
+        // over budget the term can't match.
+        let over_budget = |row: &[usize]| row.iter().all(|distance| *distance > budget);
+        if over_budget(&current) && over_budget(&previous) {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    let distance = previous.iter().copied().min().unwrap_or(term.len());
+    (distance <= budget).then_some(distance)
+}
+
+/// Scores a lowercased term against a lowercased name, or `None` when the term's
+/// characters don't all appear in order and it isn't within its typo budget of
+/// any part of the name. `original` is only used for word boundaries.
+fn score_term(original: &[char], name: &[char], term: &[char]) -> Option<u32> {
+    if term.is_empty() || name.is_empty() {
+        return None;
+    }
+    let coverage = (term.len().min(name.len()) * 100 / name.len()) as u32;
+    if term.len() <= name.len() {
+        if let Some(score) = score_exact(original, name, term, coverage) {
+            return Some(score);
+        }
+    }
+    let distance = typo_distance(name, term, typo_budget(term.len()))?;
+    if distance == 0 {
+        return None;
+    }
+    Some((150 - 50 * distance as u32 + coverage / 2).clamp(1, 199))
+}
+
+/// Scores a term whose characters all appear in the name, in order.
+fn score_exact(original: &[char], name: &[char], term: &[char], coverage: u32) -> Option<u32> {
+    if name == term {
+        return Some(1000);
+    }
+    let stem = name
+        .iter()
+        .rposition(|c| *c == '.')
+        .filter(|dot| *dot > 0)
+        .map(|dot| &name[..dot]);
+    if stem == Some(term) {
+        return Some(950);
+    }
+    if name.starts_with(term) {
+        return Some(700 + coverage);
+    }
+    if let Some(position) = name.windows(term.len()).position(|window| window == term) {
+        let boundary = if is_boundary(original, position) {
+            100
+        } else {
+            0
+        };
+        return Some(400 + boundary + coverage - (position as u32).min(50));
+    }
+
+    let mut score: i64 = 0;
+    let mut previous: Option<usize> = None;
+    let mut remaining = term.iter().peekable();
+    for (index, c) in name.iter().enumerate() {
+        let Some(wanted) = remaining.peek() else {
+            break;
+        };
+        if c != *wanted {
+            continue;
+        }
+        remaining.next();
+        score += 10;
+        if is_boundary(original, index) {
+            score += 15;
+        }
+        match previous {
+            Some(previous) if previous + 1 == index => score += 10,
+            Some(previous) => score -= (index - previous - 1).min(10) as i64,
+            None => score -= index.min(20) as i64,
+        }
+        previous = Some(index);
+    }
+    if remaining.peek().is_some() {
+        return None;
+    }
+    Some((score + coverage as i64).clamp(1, 399) as u32)
+}
+
+fn lowercase_chars(value: &str) -> Vec<char> {
+    value
+        .chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+fn score_name(name: &str, terms: &[Vec<char>]) -> Option<u32> {
+    let original = name.chars().collect::<Vec<_>>();
+    let lowered = lowercase_chars(name);
+    terms.iter().try_fold(0, |total, term| {
+        score_term(&original, &lowered, term).map(|score| total + score)
+    })
+}
+
+/// A pattern every name the term could match fits, to narrow the candidates
+/// down before scoring. With `n` typos allowed, each touching at most two
+/// neighbouring characters, one of `2n + 1` pieces of the term is left intact.
+fn name_pattern(term: &[char]) -> String {
+    let mut alternatives = vec![term
+        .iter()
+        .map(|c| regex_escape(&c.to_string()))
+        .collect::<Vec<_>>()
+        .join(".*")];
+    let pieces = 2 * typo_budget(term.len()) + 1;
+    if pieces > 1 {
+        alternatives.extend((0..pieces).map(|piece| {
+            let start = piece * term.len() / pieces;
+            let end = (piece + 1) * term.len() / pieces;
+            regex_escape(&term[start..end].iter().collect::<String>())
+        }));
+    }
+    alternatives.join("|")
+}
+
+#[get("/search")]
+pub async fn search_files(
+    query: web::Query<SearchQuery>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let files = &data.file_collection;
+    if query.q.len() > MAX_QUERY_LENGTH {
+        return Err(CustomError::InvalidQuery);
+    }
+    let terms = query
+        .q
+        .split_whitespace()
+        .map(lowercase_chars)
+        .collect::<Vec<_>>();
+    if terms.is_empty() && !query.has_filters() {
+        return Err(CustomError::InvalidQuery);
+    }
+    if let (Some(min), Some(max)) = (query.min_size, query.max_size) {
+        if min > max {
+            return Err(CustomError::InvalidQuery);
+        }
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut conditions: Vec<Bson> = terms
+        .iter()
+        .map(|term| {
+            let pattern = name_pattern(term);
+            Bson::Document(doc! {"name": {"$regex": pattern, "$options": "i"}})
+        })
+        .collect();
+    if let Some(types) = query.types.as_deref().and_then(type_filter) {
+        conditions.push(Bson::Document(types));
+    }
+    if let Some(tags) = query.tag.as_deref().map(tag_filter).transpose()?.flatten() {
+        conditions.push(Bson::Document(tags));
+    }
+    let mut size = doc! {};
+    if let Some(min) = query.min_size {
+        size.insert("$gte", min);
+    }
+    if let Some(max) = query.max_size {
+        size.insert("$lte", max);
+    }
+    if !size.is_empty() {
+        conditions.push(Bson::Document(doc! {"size": size}));
+    }
+    let mut modified = doc! {};
+    if let Some(after) = query.modified_after {
+        modified.insert("$gte", BsonDateTime::from_chrono(after));
+    }
+    if let Some(before) = query.modified_before {
+        modified.insert("$lte", BsonDateTime::from_chrono(before));
+    }
+    if !modified.is_empty() {
+        conditions.push(Bson::Document(doc! {"modified": modified}));
+    }
+    let mut filter = doc! {"owner": &*auth, "trashed": null};
+    if !conditions.is_empty() {
+        filter.insert("$and", conditions);
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! {"modified": -1})
+        .limit(MAX_CANDIDATES + 1)
+        .build();
+    let mut candidates: Vec<FileRecord> = files
+        .find(filter.clone(), options)
+        .await?
+        .try_collect()
+        .await?;
+    let truncated = candidates.len() as i64 > MAX_CANDIDATES;
+    candidates.truncate(MAX_CANDIDATES as usize);
+    let scored = candidates.len() as u64;
+    let mut ranked = candidates
+        .into_iter()
+        .filter_map(|record| score_name(&record.name, &terms).map(|score| (score, record)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let total = ranked.len() as u64;
+    // Assumes the candidates that weren't scored match about as often as
+    // those that were.
+    let approximate_total = if truncated {
+        let candidates = files.count_documents(filter, None).await?;
+        Some(candidates * total / scored.max(1))
+    } else {
+        None
+    };
+    let start = (query.offset as usize).min(ranked.len());
+    let end = (start + limit as usize).min(ranked.len());
+    let mut paths = HashMap::new();
+    let mut items = vec![];
+    let ids = ranked[start..end]
+        .iter()
+        .map(|(_, record)| record.id)
+        .collect();
+    let starred = starred_among(&data, &auth, ids).await?;
+    for (score, record) in &ranked[start..end] {
+        items.push(SearchResult {
+            metadata: FileMetadata::from(record).with_starred(starred.contains(&record.id)),
+            path: folder_path(files, &auth, record.parent, &mut paths).await?,
+            score: *score,
+        });
+    }
+
+    Ok(HttpResponse::build(StatusCode::OK).json(SearchPage {
+        items,
+        total,
+        next_offset: (end < ranked.len()).then_some(end as u32),
+        truncated,
+        approximate_total,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentQuery {
+    q: String,
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentResult {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    path: String,
+    score: f32,
+    snippet: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentPage {
+    items: Vec<ContentResult>,
+    total: u64,
+    next_offset: Option<u32>,
+}
+
+#[get("/search/content")]
+pub async fn search_content(
+    query: web::Query<ContentQuery>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let files = &data.file_collection;
+    let text = query.q.trim().to_owned();
+    if text.is_empty() || text.len() > MAX_QUERY_LENGTH {
+        return Err(CustomError::InvalidQuery);
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset;
+
+    let index = data.text_index.clone();
+    let owner = auth.to_string();
+    let results = web::block(move || index.search(&owner, &text, limit as usize, offset as usize))
+        .await
+        .map_err(|_| CustomError::InternalError)??;
+    let (hits, total) = (&results.hits, results.total);
+
+    let ids: Vec<ObjectId> = hits
+        .iter()
+        .filter_map(|hit| ObjectId::parse_str(&hit.id).ok())
+        .collect();
+    let mut records: HashMap<ObjectId, FileRecord> = files
+        .find(
+            doc! {"_id": {"$in": ids}, "owner": &*auth, "trashed": null},
+            None,
+        )
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect();
+
+    let mut paths = HashMap::new();
+    let mut items = vec![];
+    for TextHit { id, score } in hits {
+        let record = match ObjectId::parse_str(id)
+            .ok()
+            .and_then(|id| records.remove(&id))
+        {
+            Some(record) => record,
+            None => continue,
+        };
+        let snippet = match read_text(data.storage.as_ref(), &record).await {
+            Ok(body) => results.snippet(&body),
+            Err(_) => String::new(),
+        };
+        items.push(ContentResult {
+            metadata: FileMetadata::from(&record),
+            path: folder_path(files, &auth, record.parent, &mut paths).await?,
+            score: *score,
+            snippet,
+        });
+    }
+
+    let end = offset as usize + limit as usize;
+    Ok(HttpResponse::build(StatusCode::OK).json(ContentPage {
+        items,
+        total: total as u64,
+        next_offset: (end < total).then_some(end as u32),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(name: &str, query: &str) -> Option<u32> {
+        let terms = query
+            .split_whitespace()
+            .map(lowercase_chars)
+            .collect::<Vec<_>>();
+        score_name(name, &terms)
+    }
+
+    #[test]
+    fn ranks_closer_matches_higher() {
+        let ranked = [
+            score("report", "report"),
+            score("report.pdf", "report"),
+            score("report-2023.pdf", "report"),
+            score("annual-report.pdf", "report"),
+            score("annualreport.pdf", "report"),
+            score("r-e-p-o-r-t.pdf", "report"),
+            score("reprot.pdf", "report"),
+        ];
+        assert!(ranked.iter().all(Option::is_some), "{:?}", ranked);
+        assert!(
+            ranked.windows(2).all(|pair| pair[0] > pair[1]),
+            "{:?}",
+            ranked
+        );
+    }
+
+    #[test]
+    fn prefers_word_boundaries() {
+        assert!(score("MyReport.txt", "rep") > score("prepare.txt", "rep"));
+        assert!(score("my_report.txt", "mr") > score("summary.txt", "mr"));
+    }
+
+    #[test]
+    fn needs_every_term() {
+        assert!(score("budget-2023.xlsx", "budget 2023").is_some());
+        assert!(score("budget-2023.xlsx", "budget 2024").is_none());
+        assert!(score("notes.txt", "").is_some());
+    }
+
+    #[test]
+    fn tolerates_typos_in_longer_terms() {
+        // Swapped, missing, extra and wrong characters.
+        for query in ["reprot", "reort", "repoort", "repirt"] {
+            assert!(score("report.pdf", query).is_some(), "{}", query);
+        }
+        assert!(score("presentation.key", "presentaiton").is_some());
+        assert!(score("presentation.key", "prezentaton").is_some());
+        assert!(score("presentation.key", "prezentatoin").is_some());
+        assert!(score("presentation.key", "przntaton").is_none());
+        // Short terms have to match as typed.
+        assert!(score("cats.png", "cts").is_some());
+        assert!(score("cats.png", "cast").is_none());
+    }
+
+    #[test]
+    fn counts_typos_anywhere_in_the_name() {
+        let name = lowercase_chars("quarterly-report.pdf");
+        let distance = |term| typo_distance(&name, &lowercase_chars(term), 2);
+        assert_eq!(distance("report"), Some(0));
+        assert_eq!(distance("reprot"), Some(1));
+        assert_eq!(distance("repotr"), Some(1));
+        assert_eq!(distance("quartrely"), Some(1));
+        assert_eq!(distance("qarterlly"), Some(2));
+        assert_eq!(distance("qxrtxrlx"), None);
+        assert_eq!(typo_distance(&name, &lowercase_chars("qarterlly"), 1), None);
+        // Long terms only match as typed.
+        assert_eq!(typo_budget(MAX_TYPO_TERM + 1), 0);
+    }
+
+    #[test]
+    fn narrows_candidates_with_pieces_of_the_term() {
+        assert_eq!(name_pattern(&lowercase_chars("a.b")), "a.*\\..*b");
+        assert_eq!(
+            name_pattern(&lowercase_chars("report")),
+            "r.*e.*p.*o.*r.*t|re|po|rt"
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use actix_web::{get, http::StatusCode, post, route, web, HttpRequest, HttpResponse};
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{record_event, AuditEvent, AuditRecord},
+    download::Download,
+    metadata::find_file,
+    middleware::AuthenticationExtractor,
+    shares::{
+        check_ticket, count_download, find_share, shared_file, unlock_share, ShareMetadata,
+        ShareRecord,
+    },
+    utils::CustomError,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShare {
+    file: String,
+    expires: Option<DateTime<Utc>>,
+    password: Option<String>,
+    max_downloads: Option<u32>,
+}
+
+#[post("/shares")]
+pub async fn create_share(
+    req: HttpRequest,
+    body: web::Json<CreateShare>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = find_file(&data.file_collection, &auth, &body.file).await?;
+    let mut share = ShareRecord::new(&auth, record.id);
+    if let Some(expires) = body.expires {
+        if expires <= Utc::now() {
+            return Err(CustomError::InvalidShare);
+        }
+        share.expires = Some(BsonDateTime::from_chrono(expires));
+    }
+    if let Some(max_downloads) = body.max_downloads {
+        if max_downloads == 0 {
+            return Err(CustomError::InvalidShare);
+        }
+        share.max_downloads = Some(max_downloads as i64);
+    }
+    if let Some(password) = body
+        .password
+        .as_deref()
+        .filter(|password| !password.is_empty())
+    {
+        share.password =
+            Some(hash(password, DEFAULT_COST).map_err(|_| CustomError::InternalError)?);
+    }
+    data.share_collection.insert_one(&share, None).await?;
+    let event = AuditRecord::new(&data, &req, AuditEvent::ShareCreated, Some(&auth))
+        .with_entry(&record)
+        .with_detail(share.id.to_hex());
+    record_event(&data, event).await;
+    Ok(HttpResponse::build(StatusCode::CREATED).json(ShareMetadata::from(&share)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListShares {
+    file: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareListing {
+    items: Vec<ShareMetadata>,
+}
+
+#[get("/shares")]
+pub async fn list_shares(
+    query: web::Query<ListShares>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let mut filter = doc! {"owner": &*auth};
+    if let Some(file) = &query.file {
+        let record = find_file(&data.file_collection, &auth, file).await?;
+        filter.insert("file", record.id);
+    }
+    let options = FindOptions::builder().sort(doc! {"created": -1}).build();
+    let shares: Vec<ShareRecord> = data
+        .share_collection
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(ShareListing {
+        items: shares.iter().map(ShareMetadata::from).collect(),
+    }))
+}
+
+#[post("/shares/{id}/revoke")]
+pub async fn revoke_share(
+    req: HttpRequest,
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let id = ObjectId::parse_str(path.as_str()).map_err(|_| CustomError::MissingPath)?;
+    let mut share = data
+        .share_collection
+        .find_one(doc! {"_id": id, "owner": &*auth}, None)
+        .await?
+        .ok_or(CustomError::MissingPath)?;
+    data.share_collection
+        .update_one(doc! {"_id": id}, doc! {"$set": {"revoked": true}}, None)
+        .await?;
+    share.revoked = true;
+    let mut event = AuditRecord::new(&data, &req, AuditEvent::ShareRevoked, Some(&auth))
+        .with_detail(share.id.to_hex());
+    event.entry = Some(share.file);
+    record_event(&data, event).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(ShareMetadata::from(&share)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicShare {
+    name: String,
+    size: i64,
+    mime: String,
+    expires: Option<DateTime<Utc>>,
+    protected: bool,
+    downloads_remaining: Option<i64>,
+}
+
+#[get("/{token}")]
+pub async fn get_public_share(
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let share = find_share(&data, &path).await?;
+    let record = shared_file(&data, &share).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(PublicShare {
+        name: record.name,
+        size: record.size,
+        mime: record.mime,
+        expires: share.expires.map(|expires| expires.to_chrono()),
+        protected: share.password.is_some(),
+        downloads_remaining: share.max_downloads.map(|max| max - share.downloads),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockShare {
+    password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareTicket {
+    ticket: String,
+}
+
+#[post("/{token}/unlock")]
+pub async fn unlock_public_share(
+    path: web::Path<String>,
+    body: web::Json<UnlockShare>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let share = find_share(&data, &path).await?;
+    let ticket = unlock_share(&data, &share, &body.password)?;
+    Ok(HttpResponse::build(StatusCode::OK).json(ShareTicket { ticket }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareDownload {
+    ticket: Option<String>,
+    #[serde(default)]
+    attachment: bool,
+}
+
+#[route("/{token}/download", method = "GET", method = "HEAD")]
+pub async fn download_public_share(
+    req: HttpRequest,
+    path: web::Path<String>,
+    options: web::Query<ShareDownload>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let share = find_share(&data, &path).await?;
+    check_ticket(&data, &share, options.ticket.as_deref())?;
+    let record = shared_file(&data, &share).await?;
+    let download = Download::new(data.storage.clone(), &record);
+    // Resuming a partial download doesn't use up the link.
+    if download.starts_transfer(&req) {
+        count_download(&data, &share).await?;
+        let event = AuditRecord::new(&data, &req, AuditEvent::Download, None)
+            .with_entry(&record)
+            .with_detail(share.id.to_hex());
+        record_event(&data, event).await;
+    }
+    Ok(download.into_response(&req, options.attachment))
+}
@@ -0,0 +1,65 @@
+use actix_web::{get, http::StatusCode, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+
+use crate::{
+    grants::{authorize, Access},
+    metadata::FileMetadata,
+    middleware::AuthenticationExtractor,
+    stars::{star_entry, starred_entries, unstar_entry},
+    utils::CustomError,
+    AppState,
+};
+
+#[post("/files/{id}/star")]
+pub async fn star_file(
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Read).await?;
+    star_entry(&data, &auth, &record).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Unstarring doesn't need access, so entries that are no longer shared can
+/// still be cleared.
+#[post("/files/{id}/unstar")]
+pub async fn unstar_file(
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let id = ObjectId::parse_str(path.as_str()).map_err(|_| CustomError::MissingPath)?;
+    unstar_entry(&data, &auth, id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize)]
+pub struct StarredEntry {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    starred_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StarredListing {
+    items: Vec<StarredEntry>,
+}
+
+#[get("/starred")]
+pub async fn get_starred(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let items = starred_entries(&data, &auth)
+        .await?
+        .iter()
+        .map(|(star, record)| StarredEntry {
+            metadata: FileMetadata::from(record).with_starred(true),
+            starred_at: star.created.to_chrono(),
+        })
+        .collect();
+    Ok(HttpResponse::build(StatusCode::OK).json(StarredListing { items }))
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use actix_web::{get, http::StatusCode, post, web, HttpResponse};
+use mongodb::{
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grants::{authorize, Access},
+    metadata::{FileMetadata, FileRecord},
+    middleware::AuthenticationExtractor,
+    tags::{
+        tag_counts, validate_properties, validate_property, validate_tags, TagCount,
+        MAX_PROPERTIES, MAX_TAGS,
+    },
+    utils::CustomError,
+    AppState,
+};
+
+async fn update_entry(
+    data: &AppState,
+    record: &FileRecord,
+    update: Document,
+) -> Result<HttpResponse, CustomError> {
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let record = data
+        .file_collection
+        .find_one_and_update(doc! {"_id": record.id}, update, options)
+        .await?
+        .ok_or(CustomError::MissingPath)?;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditTags {
+    tags: Vec<String>,
+}
+
+#[post("/files/{id}/tags")]
+pub async fn add_tags(
+    path: web::Path<String>,
+    body: web::Json<EditTags>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Write).await?;
+    let tags = validate_tags(&body.tags)?;
+    let added = tags.iter().filter(|tag| !record.tags.contains(tag)).count();
+    if tags.is_empty() || record.tags.len() + added > MAX_TAGS {
+        return Err(CustomError::InvalidTag);
+    }
+    update_entry(
+        &data,
+        &record,
+        doc! {"$addToSet": {"tags": {"$each": tags}}},
+    )
+    .await
+}
+
+#[post("/files/{id}/tags/remove")]
+pub async fn remove_tags(
+    path: web::Path<String>,
+    body: web::Json<EditTags>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Write).await?;
+    let tags = validate_tags(&body.tags)?;
+    update_entry(&data, &record, doc! {"$pullAll": {"tags": tags}}).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetProperties {
+    properties: BTreeMap<String, String>,
+}
+
+#[post("/files/{id}/properties")]
+pub async fn set_properties(
+    path: web::Path<String>,
+    body: web::Json<SetProperties>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Write).await?;
+    validate_properties(&body.properties)?;
+    let added = body
+        .properties
+        .keys()
+        .filter(|key| !record.properties.contains_key(*key))
+        .count();
+    if body.properties.is_empty() || record.properties.len() + added > MAX_PROPERTIES {
+        return Err(CustomError::InvalidTag);
+    }
+    let mut fields = Document::new();
+    for (key, value) in &body.properties {
+        fields.insert(format!("properties.{}", key), value);
+    }
+    update_entry(&data, &record, doc! {"$set": fields}).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveProperties {
+    keys: Vec<String>,
+}
+
+#[post("/files/{id}/properties/remove")]
+pub async fn remove_properties(
+    path: web::Path<String>,
+    body: web::Json<RemoveProperties>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize(&data, &auth, &path, Access::Write).await?;
+    if body.keys.is_empty() {
+        return Err(CustomError::InvalidTag);
+    }
+    let mut fields = Document::new();
+    for key in &body.keys {
+        validate_property(key, "")?;
+        fields.insert(format!("properties.{}", key), "");
+    }
+    update_entry(&data, &record, doc! {"$unset": fields}).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagListing {
+    items: Vec<TagCount>,
+}
+
+#[get("/tags")]
+pub async fn get_tags(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let items = tag_counts(&data, &auth).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(TagListing { items }))
+}
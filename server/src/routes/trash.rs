@@ -0,0 +1,104 @@
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{record_event, AuditEvent, AuditRecord},
+    metadata::{find_trashed, FileMetadata, FileRecord},
+    middleware::AuthenticationExtractor,
+    trash::{list_trash, purge_entry, restore_entry},
+    utils::{ConflictPolicy, CustomError},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct TrashItem {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    original_path: String,
+    trashed: DateTime<Utc>,
+}
+
+impl From<&FileRecord> for TrashItem {
+    fn from(record: &FileRecord) -> Self {
+        TrashItem {
+            metadata: FileMetadata::from(record),
+            original_path: record
+                .trash
+                .as_ref()
+                .map(|origin| origin.path.clone())
+                .unwrap_or_default(),
+            trashed: record
+                .trashed
+                .map(|trashed| trashed.to_chrono())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrashListing {
+    items: Vec<TrashItem>,
+}
+
+#[get("/trash")]
+pub async fn get_trash(
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let records = list_trash(&data, &auth).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(TrashListing {
+        items: records.iter().map(TrashItem::from).collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreEntry {
+    id: String,
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+#[post("/trash/restore")]
+pub async fn restore_from_trash(
+    body: web::Json<RestoreEntry>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = find_trashed(&data.file_collection, &auth, &body.id).await?;
+    let record = restore_entry(&data, record, body.conflict).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeEntry {
+    id: String,
+}
+
+#[post("/trash/delete")]
+pub async fn delete_from_trash(
+    req: HttpRequest,
+    body: web::Json<PurgeEntry>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = find_trashed(&data.file_collection, &auth, &body.id).await?;
+    purge_entry(&data, &record).await?;
+    let event = AuditRecord::new(&data, &req, AuditEvent::Delete, Some(&auth));
+    record_event(&data, event.with_entry(&record)).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/trash/empty")]
+pub async fn empty_trash(
+    req: HttpRequest,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    for record in list_trash(&data, &auth).await? {
+        purge_entry(&data, &record).await?;
+        let event = AuditRecord::new(&data, &req, AuditEvent::Delete, Some(&auth));
+        record_event(&data, event.with_entry(&record)).await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::{
+    delete, head,
+    http::header::{self, HeaderMap},
+    options, patch, post, web, HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    activity::{record_activity, Action},
+    audit::{record_event, AuditEvent, AuditRecord},
+    grants::{authorize_folder, Access},
+    metadata::{store_content, Content, FileRecord},
+    middleware::AuthenticationExtractor,
+    quota::check_quota,
+    staging::StagedFile,
+    utils::{validate_file_name, ConflictPolicy, CustomError, Opt, TUS_VERSION},
+    AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadInfo {
+    length: u64,
+    file_name: String,
+    folder: Option<String>,
+    metadata: Option<String>,
+    created: DateTime<Utc>,
+}
+
+struct UploadPaths {
+    key: String,
+    info: PathBuf,
+    data: PathBuf,
+}
+
+impl UploadPaths {
+    fn new(opt: &Opt, user_id: &str, upload_id: &str) -> Result<Self, CustomError> {
+        let upload_id = Uuid::parse_str(upload_id).map_err(|_| CustomError::MissingPath)?;
+        let dir = opt.staging_dir(user_id);
+        Ok(UploadPaths {
+            key: format!("{}/{}", user_id, upload_id),
+            info: dir.join(format!("{}.info", upload_id)),
+            data: dir.join(format!("{}.bin", upload_id)),
+        })
+    }
+
+    async fn read_info(&self) -> Result<UploadInfo, CustomError> {
+        let raw = fs::read(&self.info)
+            .await
+            .map_err(|_| CustomError::MissingPath)?;
+        serde_json::from_slice(&raw).map_err(|_| CustomError::InternalError)
+    }
+
+    /// Removes a finished upload, whether or not storing it worked. The staged
+    /// data has been moved into storage or discarded by then, so an upload left
+    /// behind couldn't be resumed.
+    async fn close<T>(&self, stored: Result<T, CustomError>) -> Result<T, CustomError> {
+        fs::remove_file(&self.data).await.ok();
+        let removed = fs::remove_file(&self.info).await;
+        let stored = stored?;
+        removed?;
+        Ok(stored)
+    }
+
+    async fn offset(&self) -> Result<u64, CustomError> {
+        let metadata = fs::metadata(&self.data)
+            .await
+            .map_err(|_| CustomError::MissingPath)?;
+        Ok(metadata.len())
+    }
+}
+
+struct UploadGuard {
+    uploads: Arc<Mutex<HashSet<String>>>,
+    key: String,
+}
+
+impl UploadGuard {
+    fn acquire(uploads: &Arc<Mutex<HashSet<String>>>, key: &str) -> Result<Self, CustomError> {
+        if !uploads.lock().unwrap().insert(key.to_owned()) {
+            return Err(CustomError::UploadLocked);
+        }
+        Ok(UploadGuard {
+            uploads: uploads.clone(),
+            key: key.to_owned(),
+        })
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.uploads.lock().unwrap().remove(&self.key);
+    }
+}
+
+fn check_version(req: &HttpRequest) -> Result<(), CustomError> {
+    match header_str(req.headers(), "Tus-Resumable") {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(CustomError::UnsupportedVersion),
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Result<u64, CustomError> {
+    header_str(headers, name)
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or(CustomError::InvalidHeaders)
+}
+
+fn parse_metadata(raw: &str) -> Result<HashMap<String, String>, CustomError> {
+    raw.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().unwrap_or_default().to_owned();
+            let value = match parts.next() {
+                Some(encoded) => STANDARD
+                    .decode(encoded.trim())
+                    .ok()
+                    .and_then(|decoded| String::from_utf8(decoded).ok())
+                    .ok_or(CustomError::InvalidHeaders)?,
+                None => String::new(),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+async fn store_upload(
+    data: &AppState,
+    user_id: &str,
+    paths: &UploadPaths,
+    info: &UploadInfo,
+) -> Result<FileRecord, CustomError> {
+    let destination =
+        authorize_folder(data, user_id, info.folder.as_deref(), Access::Write).await?;
+    store_content(
+        data,
+        &destination.owner,
+        destination.folder,
+        &info.file_name,
+        ConflictPolicy::Fail,
+        Content::Staged(&paths.data),
+    )
+    .await
+}
+
+async fn finish_upload(
+    data: &AppState,
+    req: &HttpRequest,
+    user_id: &str,
+    paths: &UploadPaths,
+    info: &UploadInfo,
+) -> Result<(), CustomError> {
+    let stored = store_upload(data, user_id, paths, info).await;
+    let record = paths.close(stored).await?;
+    record_activity(data, user_id, &record, Action::Uploaded).await;
+    let event = AuditRecord::new(data, req, AuditEvent::Upload, Some(user_id));
+    record_event(data, event.with_entry(&record)).await;
+    Ok(())
+}
+
+#[options("/uploads")]
+pub async fn upload_options(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", "creation,termination"))
+        .insert_header(("Tus-Max-Size", data.opt.max_upload_size.to_string()))
+        .finish()
+}
+
+#[post("/uploads")]
+pub async fn create_upload(
+    req: HttpRequest,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    check_version(&req)?;
+    let id = auth.clone();
+    let length = header_u64(req.headers(), "Upload-Length")?;
+    if length > data.opt.max_upload_size {
+        return Err(CustomError::PayloadTooLarge);
+    }
+
+    let raw_metadata = header_str(req.headers(), "Upload-Metadata").map(str::to_owned);
+    let metadata = match &raw_metadata {
+        Some(raw) => parse_metadata(raw)?,
+        None => HashMap::new(),
+    };
+    let file_name = metadata
+        .get("filename")
+        .or_else(|| metadata.get("name"))
+        .ok_or(CustomError::InvalidName)?;
+    let file_name = validate_file_name(file_name)?.to_owned();
+    let folder = metadata.get("folder").cloned();
+    let destination = authorize_folder(&data, &id, folder.as_deref(), Access::Write).await?;
+    ConflictPolicy::Fail
+        .resolve(
+            &data.file_collection,
+            &destination.owner,
+            destination.folder,
+            &file_name,
+        )
+        .await?;
+    check_quota(&data, &destination.owner, length as i64).await?;
+
+    let upload_id = Uuid::new_v4().to_string();
+    let paths = UploadPaths::new(&data.opt, &id, &upload_id)?;
+    fs::create_dir_all(data.opt.staging_dir(&id)).await?;
+    let info = UploadInfo {
+        length,
+        file_name,
+        folder,
+        metadata: raw_metadata,
+        created: Utc::now(),
+    };
+    fs::File::create(&paths.data).await?;
+    fs::write(
+        &paths.info,
+        serde_json::to_vec(&info).map_err(|_| CustomError::InternalError)?,
+    )
+    .await?;
+
+    if length == 0 {
+        finish_upload(&data, &req, &id, &paths, &info).await?;
+    }
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header((
+            header::LOCATION,
+            format!("{}/{}", req.path().trim_end_matches('/'), upload_id),
+        ))
+        .finish())
+}
+
+#[head("/uploads/{upload_id}")]
+pub async fn get_upload_offset(
+    req: HttpRequest,
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    check_version(&req)?;
+    let paths = UploadPaths::new(&data.opt, &auth, &path)?;
+    let info = paths.read_info().await?;
+    let offset = paths.offset().await?;
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .insert_header(("Upload-Length", info.length.to_string()))
+        .insert_header((header::CACHE_CONTROL, "no-store"));
+    if let Some(metadata) = info.metadata {
+        response.insert_header(("Upload-Metadata", metadata));
+    }
+    Ok(response.finish())
+}
+
+#[patch("/uploads/{upload_id}")]
+pub async fn append_upload(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    check_version(&req)?;
+    if header_str(req.headers(), "Content-Type") != Some("application/offset+octet-stream") {
+        return Err(CustomError::UnsupportedMediaType);
+    }
+    let offset = header_u64(req.headers(), "Upload-Offset")?;
+    let paths = UploadPaths::new(&data.opt, &auth, &path)?;
+    let info = paths.read_info().await?;
+    let _guard = UploadGuard::acquire(&data.active_uploads, &paths.key)?;
+    if paths.offset().await? != offset {
+        return Err(CustomError::OffsetMismatch);
+    }
+
+    let mut file = StagedFile::append(data.staging_key.as_ref(), &paths.data).await?;
+    let mut written = offset;
+    let mut result = Ok(());
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => break,
+        };
+        if written + chunk.len() as u64 > info.length {
+            result = Err(CustomError::PayloadTooLarge);
+            break;
+        }
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+    drop(file);
+    result?;
+
+    if written == info.length {
+        finish_upload(&data, &req, &auth, &paths, &info).await?;
+    }
+
+    Ok(HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Upload-Offset", written.to_string()))
+        .finish())
+}
+
+#[delete("/uploads/{upload_id}")]
+pub async fn terminate_upload(
+    req: HttpRequest,
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    check_version(&req)?;
+    let paths = UploadPaths::new(&data.opt, &auth, &path)?;
+    paths.read_info().await?;
+    let _guard = UploadGuard::acquire(&data.active_uploads, &paths.key)?;
+    fs::remove_file(&paths.data).await.ok();
+    fs::remove_file(&paths.info).await?;
+
+    Ok(HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .finish())
+}
+
+pub async fn expire_uploads(staging_root: String, expiration: u64) {
+    let expiration = Duration::from_secs(expiration);
+    let mut interval = tokio::time::interval(
+        (expiration / 4).clamp(Duration::from_secs(60), Duration::from_secs(60 * 60)),
+    );
+    loop {
+        interval.tick().await;
+        if let Err(e) = remove_expired_uploads(&staging_root, expiration).await {
+            tracing::warn!("Failed to remove expired uploads: {}", e);
+        }
+    }
+}
+
+async fn is_idle(path: &Path, expiration: Duration) -> std::io::Result<bool> {
+    let metadata = match fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    Ok(metadata.modified()?.elapsed().unwrap_or_default() > expiration)
+}
+
+async fn remove_expired_uploads(staging_root: &str, expiration: Duration) -> std::io::Result<()> {
+    let mut users = match fs::read_dir(staging_root).await {
+        Ok(users) => users,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(user) = users.next_entry().await? {
+        if !user.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut entries = fs::read_dir(user.path()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if let Err(e) = remove_if_expired(&entry, expiration).await {
+                tracing::warn!("Failed to remove expired upload {}: {}", path.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes one entry of a user's staging directory once it has been idle for
+/// `expiration`. Folders are left behind by archive extraction.
+async fn remove_if_expired(entry: &fs::DirEntry, expiration: Duration) -> std::io::Result<()> {
+    let path = entry.path();
+    if entry.file_type().await?.is_dir() {
+        if is_idle(&path, expiration).await? {
+            fs::remove_dir_all(&path).await?;
+        }
+        return Ok(());
+    }
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("info") => {
+            let data_path = path.with_extension("bin");
+            let idle_path = if fs::try_exists(&data_path).await? {
+                data_path.clone()
+            } else {
+                path.clone()
+            };
+            if is_idle(&idle_path, expiration).await? {
+                fs::remove_file(&data_path).await.ok();
+                fs::remove_file(&path).await?;
+            }
+        }
+        Some("bin") => {
+            if !fs::try_exists(path.with_extension("info")).await?
+                && is_idle(&path, expiration).await?
+            {
+                fs::remove_file(&path).await?;
+            }
+        }
+        _ => {
+            if is_idle(&path, expiration).await? {
+                fs::remove_file(&path).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn uploads_over_quota_are_removed() {
+        let dir = env::temp_dir().join(format!("fizap-uploads-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let paths = UploadPaths {
+            key: "user/upload".to_string(),
+            info: dir.join("upload.info"),
+            data: dir.join("upload.bin"),
+        };
+        fs::write(&paths.info, b"{}").await.unwrap();
+        fs::write(&paths.data, b"data").await.unwrap();
+
+        let closed = paths.close::<()>(Err(CustomError::QuotaExceeded)).await;
+        assert!(matches!(closed, Err(CustomError::QuotaExceeded)));
+        assert!(!fs::try_exists(&paths.info).await.unwrap());
+        assert!(!fs::try_exists(&paths.data).await.unwrap());
+        assert!(matches!(
+            paths.read_info().await,
+            Err(CustomError::MissingPath)
+        ));
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use actix_web::{get, http::StatusCode, post, route, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use mongodb::bson::DateTime as BsonDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    activity::{record_activity, Action},
+    audit::{record_event, AuditEvent, AuditRecord},
+    download::Download,
+    grants::{authorize_file, Access},
+    metadata::FileMetadata,
+    middleware::AuthenticationExtractor,
+    routes::files::DownloadOptions,
+    utils::CustomError,
+    versions::{find_version, list_versions, prune_versions, restore_version, VersionMetadata},
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct VersionListing {
+    items: Vec<VersionMetadata>,
+}
+
+#[get("/files/{id}/versions")]
+pub async fn get_versions(
+    path: web::Path<String>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Read).await?;
+    let versions = list_versions(&data, &record).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(VersionListing {
+        items: versions.iter().map(VersionMetadata::from).collect(),
+    }))
+}
+
+#[route("/files/{id}/versions/{version}", method = "GET", method = "HEAD")]
+pub async fn download_version(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    options: web::Query<DownloadOptions>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let (id, version) = path.into_inner();
+    let record = authorize_file(&data, &auth, &id, Access::Read).await?;
+    let version = find_version(&data, &record, &version).await?;
+    let download = Download::with_key(
+        data.storage.clone(),
+        &version.as_record(&record),
+        version.content_key(),
+    );
+    if download.starts_transfer(&req) {
+        let event = AuditRecord::new(&data, &req, AuditEvent::Download, Some(&auth))
+            .with_entry(&record)
+            .with_detail(format!("version {}", version.id));
+        record_event(&data, event).await;
+    }
+    Ok(download.into_response(&req, options.attachment))
+}
+
+#[post("/files/{id}/versions/{version}/restore")]
+pub async fn restore_file_version(
+    path: web::Path<(String, String)>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let (id, version) = path.into_inner();
+    let record = authorize_file(&data, &auth, &id, Access::Write).await?;
+    let version = find_version(&data, &record, &version).await?;
+    let record = restore_version(&data, record, version).await?;
+    record_activity(&data, &auth, &record, Action::Modified).await;
+    Ok(HttpResponse::build(StatusCode::OK).json(FileMetadata::from(&record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PruneVersions {
+    keep: Option<usize>,
+    before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneResult {
+    removed: u64,
+}
+
+#[post("/files/{id}/versions/prune")]
+pub async fn prune_file_versions(
+    path: web::Path<String>,
+    body: web::Json<PruneVersions>,
+    auth: AuthenticationExtractor,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, CustomError> {
+    let record = authorize_file(&data, &auth, &path, Access::Write).await?;
+    // Without `keep`, an age cutoff alone only removes versions older than it.
+    // Asking for neither is refused rather than taken as removing everything.
+    let keep = match (body.keep, body.before) {
+        (None, None) => return Err(CustomError::InvalidPrune),
+        (None, Some(_)) => usize::MAX,
+        (Some(keep), _) => keep,
+    };
+    let before = body.before.map(BsonDateTime::from_chrono);
+    let removed = prune_versions(&data, &record, keep, before).await?;
+    Ok(HttpResponse::build(StatusCode::OK).json(PruneResult { removed }))
+}
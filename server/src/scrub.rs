@@ -0,0 +1,273 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{StreamExt, TryStreamExt};
+use mongodb::{bson::doc, Collection};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    blobs::{blob_key, BlobRecord},
+    metadata::FileRecord,
+    storage::Storage,
+    versions::VersionRecord,
+};
+
+/// Unreferenced objects younger than this may belong to an upload that is
+/// still being stored, so they aren't reported.
+const ORPHAN_GRACE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default)]
+pub struct ScrubSummary {
+    pub checked: u64,
+    pub recorded: u64,
+    pub corrupt: u64,
+    pub missing: u64,
+    pub orphaned: u64,
+    pub quarantined: u64,
+}
+
+impl ScrubSummary {
+    pub fn problems(&self) -> u64 {
+        self.corrupt + self.missing + self.orphaned
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Outcome {
+    Hashed(u64, String),
+    Missing,
+    Unreadable(String),
+}
+
+/// What a metadata record expects to find in storage.
+struct Expected {
+    description: String,
+    key: String,
+    size: u64,
+    checksum: Option<String>,
+    blob: Option<String>,
+}
+
+impl From<&FileRecord> for Expected {
+    fn from(record: &FileRecord) -> Self {
+        Expected {
+            description: format!("file {} of {}", record.id, record.owner),
+            key: record.content_key(),
+            size: record.size as u64,
+            checksum: record.checksum.clone(),
+            blob: record.blob.clone(),
+        }
+    }
+}
+
+impl From<&VersionRecord> for Expected {
+    fn from(version: &VersionRecord) -> Self {
+        Expected {
+            description: format!("version {} of file {}", version.id, version.file),
+            key: version.content_key(),
+            size: version.size as u64,
+            checksum: version.checksum.clone(),
+            blob: version.blob.clone(),
+        }
+    }
+}
+
+fn quarantine_key(key: &str) -> String {
+    format!("quarantine/{}", key)
+}
+
+/// Keys holding derived or internal data rather than file content.
+fn is_untracked(key: &str) -> bool {
+    key.starts_with("thumbnails/")
+        || key.starts_with("quarantine/")
+        || key.split('/').any(|segment| segment.starts_with('.'))
+}
+
+async fn hash_object(storage: &dyn Storage, key: &str, stored: u64) -> io::Result<(u64, String)> {
+    let mut content = storage.read(key, 0, stored).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = content.next().await {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+struct Scrubber<'a> {
+    storage: &'a dyn Storage,
+    quarantine: bool,
+    stored: HashMap<String, (u64, SystemTime)>,
+    outcomes: HashMap<String, Outcome>,
+    moved: HashSet<String>,
+    summary: ScrubSummary,
+}
+
+impl Scrubber<'_> {
+    async fn outcome(&mut self, key: &str) -> Outcome {
+        if let Some(outcome) = self.outcomes.get(key) {
+            return outcome.clone();
+        }
+        let outcome = match self.stored.get(key) {
+            None => Outcome::Missing,
+            Some(&(stored, _)) => match hash_object(self.storage, key, stored).await {
+                Ok((size, checksum)) => Outcome::Hashed(size, checksum),
+                Err(e) => Outcome::Unreadable(e.to_string()),
+            },
+        };
+        self.summary.checked += 1;
+        self.outcomes.insert(key.to_owned(), outcome.clone());
+        outcome
+    }
+
+    async fn move_to_quarantine(&mut self, key: &str) {
+        if !self.quarantine || !self.moved.insert(key.to_owned()) {
+            return;
+        }
+        let target = quarantine_key(key);
+        match self.storage.rename(key, &target).await {
+            Ok(()) => {
+                tracing::info!("Moved {} to {}", key, target);
+                self.summary.quarantined += 1;
+            }
+            Err(e) => tracing::warn!("Failed to quarantine {}: {}", key, e),
+        }
+    }
+
+    /// Checks the content of a record, returning its checksum when the record
+    /// doesn't have one yet and the content looks intact.
+    async fn check(&mut self, expected: Expected) -> Option<String> {
+        let problem = match self.outcome(&expected.key).await {
+            Outcome::Missing => {
+                tracing::warn!(
+                    "Missing content for {} at {}",
+                    expected.description,
+                    expected.key
+                );
+                self.summary.missing += 1;
+                return None;
+            }
+            Outcome::Unreadable(e) => format!("unreadable: {}", e),
+            Outcome::Hashed(size, _) if size != expected.size => {
+                format!("{} bytes instead of {}", size, expected.size)
+            }
+            // Blobs are named after the checksum of their content.
+            Outcome::Hashed(_, checksum) => {
+                match expected.checksum.as_ref().or(expected.blob.as_ref()) {
+                    Some(wanted) if *wanted != checksum => {
+                        format!("checksum {} instead of {}", checksum, wanted)
+                    }
+                    _ if expected.checksum.is_none() => return Some(checksum),
+                    _ => return None,
+                }
+            }
+        };
+        tracing::warn!(
+            "Corrupt content for {} at {}: {}",
+            expected.description,
+            expected.key,
+            problem
+        );
+        self.summary.corrupt += 1;
+        self.move_to_quarantine(&expected.key).await;
+        None
+    }
+}
+
+/// Re-hashes the content of every file and version against its metadata and
+/// looks for stored objects nothing refers to. Files stored before checksums
+/// were kept get theirs recorded. With `quarantine`, corrupt and orphaned
+/// objects are moved under `quarantine/`.
+pub async fn scrub(
+    files: &Collection<FileRecord>,
+    versions: &Collection<VersionRecord>,
+    blobs: &Collection<BlobRecord>,
+    storage: &dyn Storage,
+    quarantine: bool,
+) -> io::Result<ScrubSummary> {
+    let stored = storage
+        .list("")
+        .await?
+        .into_iter()
+        .map(|object| (object.key, (object.size, object.modified)))
+        .collect();
+    let mut scrubber = Scrubber {
+        storage,
+        quarantine,
+        stored,
+        outcomes: HashMap::new(),
+        moved: HashSet::new(),
+        summary: ScrubSummary::default(),
+    };
+
+    // Hashing can take longer than a cursor stays open, so records are loaded up front.
+    let records: Vec<FileRecord> = files
+        .find(doc! {"kind": "file"}, None)
+        .await
+        .map_err(io::Error::other)?
+        .try_collect()
+        .await
+        .map_err(io::Error::other)?;
+    for record in records {
+        if let Some(checksum) = scrubber.check(Expected::from(&record)).await {
+            files
+                .update_one(
+                    doc! {"_id": record.id, "checksum": null},
+                    doc! {"$set": {"checksum": checksum}},
+                    None,
+                )
+                .await
+                .map_err(io::Error::other)?;
+            scrubber.summary.recorded += 1;
+        }
+    }
+
+    let records: Vec<VersionRecord> = versions
+        .find(None, None)
+        .await
+        .map_err(io::Error::other)?
+        .try_collect()
+        .await
+        .map_err(io::Error::other)?;
+    for version in records {
+        if let Some(checksum) = scrubber.check(Expected::from(&version)).await {
+            versions
+                .update_one(
+                    doc! {"_id": version.id, "checksum": null},
+                    doc! {"$set": {"checksum": checksum}},
+                    None,
+                )
+                .await
+                .map_err(io::Error::other)?;
+            scrubber.summary.recorded += 1;
+        }
+    }
+
+    // Released blobs are still tracked until they are collected.
+    let mut referenced: HashSet<String> = scrubber.outcomes.keys().cloned().collect();
+    let mut records = blobs.find(None, None).await.map_err(io::Error::other)?;
+    while let Some(blob) = records.try_next().await.map_err(io::Error::other)? {
+        referenced.insert(blob_key(&blob.hash));
+    }
+
+    let cutoff = SystemTime::now() - ORPHAN_GRACE;
+    let mut orphans: Vec<String> = scrubber
+        .stored
+        .iter()
+        .filter(|(key, (_, modified))| {
+            !referenced.contains(*key) && !is_untracked(key) && *modified < cutoff
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    orphans.sort();
+    for key in orphans {
+        tracing::warn!("Orphaned object {}", key);
+        scrubber.summary.orphaned += 1;
+        scrubber.move_to_quarantine(&key).await;
+    }
+    Ok(scrubber.summary)
+}
@@ -0,0 +1,218 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::IndexOptions,
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::FileRecord,
+    routes::auth::{generate_token, Claims},
+    utils::{AppState, CustomError},
+};
+
+const TICKET_LIFETIME: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub token: String,
+    pub owner: String,
+    pub file: ObjectId,
+    pub created: BsonDateTime,
+    pub expires: Option<BsonDateTime>,
+    pub password: Option<String>,
+    pub max_downloads: Option<i64>,
+    pub downloads: i64,
+    pub revoked: bool,
+}
+
+impl ShareRecord {
+    pub fn new(owner: &str, file: ObjectId) -> Self {
+        ShareRecord {
+            id: ObjectId::new(),
+            token: generate_share_token(),
+            owner: owner.to_owned(),
+            file,
+            created: BsonDateTime::now(),
+            expires: None,
+            password: None,
+            max_downloads: None,
+            downloads: 0,
+            revoked: false,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        !self.revoked
+            && self
+                .expires
+                .is_none_or(|expires| expires > BsonDateTime::now())
+            && self.max_downloads.is_none_or(|max| self.downloads < max)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareMetadata {
+    id: String,
+    token: String,
+    url: String,
+    file: String,
+    created: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    protected: bool,
+    max_downloads: Option<i64>,
+    downloads: i64,
+    revoked: bool,
+    available: bool,
+}
+
+impl From<&ShareRecord> for ShareMetadata {
+    fn from(share: &ShareRecord) -> Self {
+        ShareMetadata {
+            id: share.id.to_hex(),
+            token: share.token.clone(),
+            url: format!("/s/{}", share.token),
+            file: share.file.to_hex(),
+            created: share.created.to_chrono(),
+            expires: share.expires.map(|expires| expires.to_chrono()),
+            protected: share.password.is_some(),
+            max_downloads: share.max_downloads,
+            downloads: share.downloads,
+            revoked: share.revoked,
+            available: share.is_available(),
+        }
+    }
+}
+
+fn generate_share_token() -> String {
+    let bytes = [
+        *uuid::Uuid::new_v4().as_bytes(),
+        *uuid::Uuid::new_v4().as_bytes(),
+    ]
+    .concat();
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub async fn create_indexes(shares: &Collection<ShareRecord>) -> mongodb::error::Result<()> {
+    shares
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"token": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    shares
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"owner": 1, "file": 1})
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn find_share(data: &AppState, token: &str) -> Result<ShareRecord, CustomError> {
+    let share = data
+        .share_collection
+        .find_one(doc! {"token": token}, None)
+        .await?
+        .ok_or(CustomError::MissingPath)?;
+    if !share.is_available() {
+        return Err(CustomError::ShareUnavailable);
+    }
+    Ok(share)
+}
+
+pub async fn shared_file(data: &AppState, share: &ShareRecord) -> Result<FileRecord, CustomError> {
+    data.file_collection
+        .find_one(
+            doc! {"_id": share.file, "owner": &share.owner, "kind": "file", "trashed": null},
+            None,
+        )
+        .await?
+        .ok_or(CustomError::ShareUnavailable)
+}
+
+/// Counts a download against the link, failing if it ran out in the meantime.
+pub async fn count_download(data: &AppState, share: &ShareRecord) -> Result<(), CustomError> {
+    let now = BsonDateTime::now();
+    data.share_collection
+        .find_one_and_update(
+            doc! {
+                "_id": share.id,
+                "revoked": false,
+                "$and": [
+                    {"$or": [{"expires": null}, {"expires": {"$gt": now}}]},
+                    {"$or": [
+                        {"max_downloads": null},
+                        {"$expr": {"$lt": ["$downloads", "$max_downloads"]}},
+                    ]},
+                ],
+            },
+            doc! {"$inc": {"downloads": 1}},
+            None,
+        )
+        .await?
+        .map(|_| ())
+        .ok_or(CustomError::ShareUnavailable)
+}
+
+fn ticket_secret(data: &AppState) -> String {
+    format!("{}:share", data.config.jwt_secret)
+}
+
+pub fn unlock_share(
+    data: &AppState,
+    share: &ShareRecord,
+    password: &str,
+) -> Result<String, CustomError> {
+    let hash = match &share.password {
+        Some(hash) => hash,
+        None => return Err(CustomError::InvalidShare),
+    };
+    if !bcrypt::verify(password, hash).unwrap_or(false) {
+        return Err(CustomError::PasswordRequired);
+    }
+    Ok(generate_token(
+        share.token.clone(),
+        ticket_secret(data),
+        TICKET_LIFETIME,
+    ))
+}
+
+pub fn check_ticket(
+    data: &AppState,
+    share: &ShareRecord,
+    ticket: Option<&str>,
+) -> Result<(), CustomError> {
+    if share.password.is_none() {
+        return Ok(());
+    }
+    let claims = ticket.and_then(|ticket| {
+        decode::<Claims>(
+            ticket,
+            &DecodingKey::from_secret(ticket_secret(data).as_bytes()),
+            &Validation::default(),
+        )
+        .ok()
+    });
+    match claims {
+        Some(claims) if claims.claims.id == share.token => Ok(()),
+        _ => Err(CustomError::PasswordRequired),
+    }
+}
+
+pub async fn remove_shares(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    data.share_collection
+        .delete_many(doc! {"file": record.id}, None)
+        .await?;
+    Ok(())
+}
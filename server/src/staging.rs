@@ -0,0 +1,336 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    XChaCha20, XNonce,
+};
+use chacha20poly1305::Key;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use crate::keys::{unwrap_key, wrap_key};
+
+/// Where the staging key is kept, wrapped with the master key, next to the
+/// files it encrypts.
+const KEY_FILE: &str = ".staging-key";
+const KEY_OWNER: &str = "staging";
+
+/// Encrypts files while they wait in the staging directory, so uploads and
+/// extracted archives aren't left on disk in plaintext when encryption at rest
+/// is on. Staged files are only ever appended to, so a keystream bound to the
+/// path of the file keeps their size and lets them be read from any offset.
+///
+/// The key is wrapped with the master key like the data keys are, and
+/// re-wrapped when the master key is rotated, so uploads in progress can
+/// still be read afterwards.
+#[derive(Clone)]
+pub struct StagingKey(Key);
+
+impl fmt::Debug for StagingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StagingKey").finish_non_exhaustive()
+    }
+}
+
+impl StagingKey {
+    /// The key earlier versions derived from the master key, which becomes
+    /// the stored key the first time one is needed so that uploads they
+    /// staged still resume.
+    fn derive(master: &Key) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"FiZap staging key\0");
+        hasher.update(master);
+        StagingKey(hasher.finalize())
+    }
+
+    /// Unwraps the staging key kept in `root`, storing one first if there
+    /// isn't one yet.
+    pub fn load(master: &Key, root: &Path) -> io::Result<Self> {
+        let path = root.join(KEY_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(wrapped) => unwrap_key(master, KEY_OWNER, wrapped.trim())
+                .map(StagingKey)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("The master key can't unwrap {}", path.display()),
+                    )
+                }),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                std::fs::create_dir_all(root)?;
+                let key = Self::derive(master);
+                match OpenOptions::new().write(true).create_new(true).open(&path) {
+                    Ok(mut file) => {
+                        file.write_all(wrap_key(master, KEY_OWNER, &key.0).as_bytes())?;
+                        file.sync_all()?;
+                        Ok(key)
+                    }
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => Self::load(master, root),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn generate() -> Self {
+        use chacha20poly1305::{aead::OsRng, KeyInit, XChaCha20Poly1305};
+
+        StagingKey(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    fn cipher(&self, path: &Path, offset: u64) -> XChaCha20 {
+        let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+        let mut cipher = XChaCha20::new(&self.0, XNonce::from_slice(&digest[..24]));
+        cipher.seek(offset);
+        cipher
+    }
+}
+
+/// Re-wraps the staging key kept in `root` with `new`. Returns false when
+/// there's nothing to do, because no key has been stored yet or it already
+/// uses `new`, so an interrupted rotation can be run again.
+pub fn rotate_staging_key(root: &Path, old: &Key, new: &Key) -> io::Result<bool> {
+    let path = root.join(KEY_FILE);
+    let wrapped = match std::fs::read_to_string(&path) {
+        Ok(wrapped) => wrapped,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if unwrap_key(new, KEY_OWNER, wrapped.trim()).is_some() {
+        return Ok(false);
+    }
+    let key = unwrap_key(old, KEY_OWNER, wrapped.trim()).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("The old master key can't unwrap {}", path.display()),
+        )
+    })?;
+    // Replaced in one step, so a failed rotation leaves the old key readable.
+    let temporary = root.join(format!("{}.new", KEY_FILE));
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(wrap_key(new, KEY_OWNER, &key).as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(true)
+}
+
+/// A staged file, read and written through the staging key when there is one.
+pub struct StagedFile {
+    file: fs::File,
+    cipher: Option<XChaCha20>,
+}
+
+impl StagedFile {
+    pub async fn create(key: Option<&StagingKey>, path: &Path) -> io::Result<Self> {
+        Ok(StagedFile {
+            file: fs::File::create(path).await?,
+            cipher: key.map(|key| key.cipher(path, 0)),
+        })
+    }
+
+    pub async fn open(key: Option<&StagingKey>, path: &Path) -> io::Result<Self> {
+        Ok(StagedFile {
+            file: fs::File::open(path).await?,
+            cipher: key.map(|key| key.cipher(path, 0)),
+        })
+    }
+
+    pub async fn append(key: Option<&StagingKey>, path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().append(true).open(path).await?;
+        let offset = file.metadata().await?.len();
+        Ok(StagedFile {
+            file,
+            cipher: key.map(|key| key.cipher(path, offset)),
+        })
+    }
+
+    pub async fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buffer).await?;
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(&mut buffer[..read]);
+        }
+        Ok(read)
+    }
+
+    pub async fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact(buffer).await?;
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(buffer);
+        }
+        Ok(())
+    }
+
+    pub async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        match &mut self.cipher {
+            Some(cipher) => {
+                let mut sealed = buffer.to_vec();
+                cipher.apply_keystream(&mut sealed);
+                self.file.write_all(&sealed).await
+            }
+            None => self.file.write_all(buffer).await,
+        }
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
+
+/// The blocking counterpart of `StagedFile`, for archive readers that need to
+/// seek around the file.
+pub struct SyncStagedFile {
+    file: std::fs::File,
+    cipher: Option<XChaCha20>,
+}
+
+impl SyncStagedFile {
+    pub fn create(key: Option<&StagingKey>, path: &Path) -> io::Result<Self> {
+        Ok(SyncStagedFile {
+            file: std::fs::File::create(path)?,
+            cipher: key.map(|key| key.cipher(path, 0)),
+        })
+    }
+
+    pub fn open(key: Option<&StagingKey>, path: &Path) -> io::Result<Self> {
+        Ok(SyncStagedFile {
+            file: std::fs::File::open(path)?,
+            cipher: key.map(|key| key.cipher(path, 0)),
+        })
+    }
+}
+
+impl Read for SyncStagedFile {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buffer)?;
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(&mut buffer[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl Write for SyncStagedFile {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match &mut self.cipher {
+            Some(cipher) => {
+                let mut sealed = buffer.to_vec();
+                cipher.apply_keystream(&mut sealed);
+                // The keystream has moved past the whole buffer, so all of it
+                // has to be written.
+                self.file.write_all(&sealed)?;
+                Ok(buffer.len())
+            }
+            None => self.file.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for SyncStagedFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let offset = self.file.seek(position)?;
+        if let Some(cipher) = &mut self.cipher {
+            cipher.seek(offset);
+        }
+        Ok(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn staged_files_read_back_from_any_offset() {
+        let key = StagingKey::generate();
+        let path = env::temp_dir().join(format!("fizap-staged-{}", uuid::Uuid::new_v4()));
+
+        let mut file = StagedFile::create(Some(&key), &path).await.unwrap();
+        file.write_all(b"hello, ").await.unwrap();
+        file.flush().await.unwrap();
+        let mut file = StagedFile::append(Some(&key), &path).await.unwrap();
+        file.write_all(b"world").await.unwrap();
+        file.flush().await.unwrap();
+
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(on_disk.len(), 12);
+        assert_ne!(on_disk, b"hello, world");
+
+        let mut file = StagedFile::open(Some(&key), &path).await.unwrap();
+        let mut buffer = [0; 12];
+        file.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello, world");
+
+        let mut file = SyncStagedFile::open(Some(&key), &path).unwrap();
+        file.seek(SeekFrom::Start(7)).unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_resume_after_the_master_key_is_rotated() {
+        let root = env::temp_dir().join(format!("fizap-staging-{}", uuid::Uuid::new_v4()));
+        let path = root.join("upload.bin");
+        let old = Key::from([1; 32]);
+        let new = Key::from([2; 32]);
+
+        let key = StagingKey::load(&old, &root).unwrap();
+        let mut file = StagedFile::create(Some(&key), &path).await.unwrap();
+        file.write_all(b"hello, ").await.unwrap();
+        file.flush().await.unwrap();
+
+        assert!(rotate_staging_key(&root, &old, &new).unwrap());
+        assert!(!rotate_staging_key(&root, &old, &new).unwrap());
+        assert!(StagingKey::load(&old, &root).is_err());
+
+        let key = StagingKey::load(&new, &root).unwrap();
+        let mut file = StagedFile::append(Some(&key), &path).await.unwrap();
+        file.write_all(b"world").await.unwrap();
+        file.flush().await.unwrap();
+
+        let mut file = StagedFile::open(Some(&key), &path).await.unwrap();
+        let mut buffer = [0; 12];
+        file.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello, world");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_derived_key_for_uploads_staged_before_it_was_stored() {
+        let root = env::temp_dir().join(format!("fizap-staging-{}", uuid::Uuid::new_v4()));
+        let master = Key::from([3; 32]);
+        let key = StagingKey::load(&master, &root).unwrap();
+        assert_eq!(key.0, StagingKey::derive(&master).0);
+        assert_eq!(StagingKey::load(&master, &root).unwrap().0, key.0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn staged_files_are_plain_without_a_key() {
+        let path = env::temp_dir().join(format!("fizap-staged-{}", uuid::Uuid::new_v4()));
+        let mut file = StagedFile::create(None, &path).await.unwrap();
+        file.write_all(b"plain").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"plain");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::{FindOptions, IndexOptions, UpdateOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grants::{authorize, Access},
+    metadata::FileRecord,
+    utils::{AppState, CustomError},
+};
+
+/// Stars belong to the user who set them, so an entry shared with several
+/// accounts can be starred by each of them separately.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: String,
+    pub entry: ObjectId,
+    pub created: BsonDateTime,
+}
+
+pub async fn create_indexes(stars: &Collection<StarRecord>) -> mongodb::error::Result<()> {
+    stars
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"user": 1, "entry": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    stars
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"user": 1, "created": -1})
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn star_entry(
+    data: &AppState,
+    user: &str,
+    record: &FileRecord,
+) -> Result<(), CustomError> {
+    data.star_collection
+        .update_one(
+            doc! {"user": user, "entry": record.id},
+            doc! {"$setOnInsert": {"_id": ObjectId::new(), "created": BsonDateTime::now()}},
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn unstar_entry(data: &AppState, user: &str, entry: ObjectId) -> Result<(), CustomError> {
+    data.star_collection
+        .delete_one(doc! {"user": user, "entry": entry}, None)
+        .await?;
+    Ok(())
+}
+
+/// Which of `entries` `user` has starred.
+pub async fn starred_among(
+    data: &AppState,
+    user: &str,
+    entries: Vec<ObjectId>,
+) -> Result<HashSet<ObjectId>, CustomError> {
+    let stars: Vec<StarRecord> = data
+        .star_collection
+        .find(doc! {"user": user, "entry": {"$in": entries}}, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(stars.into_iter().map(|star| star.entry).collect())
+}
+
+/// The entries `user` starred and can still see, newest star first.
+pub async fn starred_entries(
+    data: &AppState,
+    user: &str,
+) -> Result<Vec<(StarRecord, FileRecord)>, CustomError> {
+    let options = FindOptions::builder()
+        .sort(doc! {"created": -1, "_id": -1})
+        .build();
+    let stars: Vec<StarRecord> = data
+        .star_collection
+        .find(doc! {"user": user}, options)
+        .await?
+        .try_collect()
+        .await?;
+    let mut starred = vec![];
+    for star in stars {
+        match authorize(data, user, &star.entry.to_hex(), Access::Read).await {
+            Ok(record) => starred.push((star, record)),
+            Err(CustomError::MissingPath) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(starred)
+}
+
+pub async fn remove_stars(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    data.star_collection
+        .delete_many(doc! {"entry": record.id}, None)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::{fmt::Debug, io, path::Path, sync::Arc, time::SystemTime};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use futures_util::stream::LocalBoxStream;
+
+use self::{local::LocalStorage, memory::MemoryStorage, s3::S3Storage};
+use crate::utils::{Config, Opt};
+
+pub mod encrypted;
+pub mod local;
+pub mod memory;
+pub mod s3;
+
+pub type ByteStream = LocalBoxStream<'static, io::Result<Bytes>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StorageKind {
+    Local,
+    Memory,
+    S3,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[async_trait(?Send)]
+pub trait Storage: Debug + Send + Sync {
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()>;
+
+    async fn read(&self, key: &str, start: u64, length: u64) -> io::Result<ByteStream>;
+
+    async fn get(&self, key: &str) -> io::Result<Bytes>;
+
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()>;
+
+    async fn copy(&self, from: &str, to: &str) -> io::Result<()>;
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.copy(from, to).await?;
+        self.delete(from).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>>;
+}
+
+pub fn init(opt: &Opt, config: &Config) -> Arc<dyn Storage> {
+    match opt.storage {
+        StorageKind::Local => Arc::new(LocalStorage::new(&opt.storage_root)),
+        StorageKind::Memory => Arc::new(MemoryStorage::default()),
+        StorageKind::S3 => {
+            Arc::new(S3Storage::new(opt, config).expect("Failed to configure S3 storage"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::ErrorKind};
+
+    use actix_web::web::Bytes;
+    use clap::Parser;
+    use futures_util::TryStreamExt;
+    use uuid::Uuid;
+
+    use super::{local::LocalStorage, memory::MemoryStorage, s3::S3Storage, Storage};
+    use crate::utils::{Config, Opt};
+
+    async fn read_all(
+        storage: &dyn Storage,
+        key: &str,
+        start: u64,
+        length: u64,
+    ) -> std::io::Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = storage
+            .read(key, start, length)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(chunks.concat())
+    }
+
+    /// What every backend has to do the same way. Keys are kept under
+    /// `prefix`, so backends that outlive the test can share a bucket.
+    async fn check_contract(storage: &dyn Storage, prefix: &str) {
+        let key = |name: &str| format!("{}/{}", prefix, name);
+        let content = Bytes::from_static(b"0123456789");
+
+        storage.put(&key("a"), content.clone()).await.unwrap();
+        assert_eq!(storage.get(&key("a")).await.unwrap(), content);
+        assert_eq!(
+            read_all(storage, &key("a"), 0, u64::MAX).await.unwrap(),
+            b"0123456789"
+        );
+        assert_eq!(read_all(storage, &key("a"), 3, 4).await.unwrap(), b"3456");
+        assert_eq!(read_all(storage, &key("a"), 8, 100).await.unwrap(), b"89");
+
+        let source = env::temp_dir().join(format!("fizap-storage-{}", Uuid::new_v4()));
+        tokio::fs::write(&source, b"from a file").await.unwrap();
+        storage.put_file(&key("nested/b"), &source).await.unwrap();
+        assert!(!source.exists());
+        assert_eq!(
+            storage.get(&key("nested/b")).await.unwrap(),
+            Bytes::from_static(b"from a file")
+        );
+
+        storage.copy(&key("a"), &key("c")).await.unwrap();
+        assert_eq!(storage.get(&key("c")).await.unwrap(), content);
+        storage.rename(&key("c"), &key("d")).await.unwrap();
+        assert_eq!(storage.get(&key("d")).await.unwrap(), content);
+
+        let mut listed: Vec<(String, u64)> = storage
+            .list(&format!("{}/", prefix))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| (object.key, object.size))
+            .collect();
+        listed.sort();
+        assert_eq!(
+            listed,
+            vec![(key("a"), 10), (key("d"), 10), (key("nested/b"), 11)]
+        );
+
+        for name in ["a", "d", "nested/b"] {
+            storage.delete(&key(name)).await.unwrap();
+        }
+        storage.delete(&key("a")).await.unwrap();
+        assert!(storage
+            .list(&format!("{}/", prefix))
+            .await
+            .unwrap()
+            .is_empty());
+
+        for missing in ["a", "c"] {
+            let error = storage.get(&key(missing)).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+            let error = read_all(storage, &key(missing), 0, 10).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+        }
+        assert!(storage.copy(&key("a"), &key("e")).await.is_err());
+        assert!(storage.rename(&key("a"), &key("e")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_storage_keeps_the_contract() {
+        check_contract(&MemoryStorage::default(), "user").await;
+    }
+
+    #[tokio::test]
+    async fn local_storage_keeps_the_contract() {
+        let root = env::temp_dir().join(format!("fizap-local-{}", Uuid::new_v4()));
+        check_contract(&LocalStorage::new(root.to_str().unwrap()), "user").await;
+        tokio::fs::remove_dir_all(&root).await.ok();
+    }
+
+    /// Runs against the real bucket named by `FIZAP_TEST_S3_BUCKET`, with the
+    /// usual `S3_ACCESS_KEY` and `S3_SECRET_KEY`, and `FIZAP_TEST_S3_ENDPOINT`
+    /// for anything other than AWS. Run it with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs FIZAP_TEST_S3_BUCKET"]
+    async fn s3_storage_keeps_the_contract() {
+        let bucket = env::var("FIZAP_TEST_S3_BUCKET").expect("FIZAP_TEST_S3_BUCKET isn't set");
+        let mut args = vec![String::from("server"), format!("--s3-bucket={}", bucket)];
+        if let Ok(endpoint) = env::var("FIZAP_TEST_S3_ENDPOINT") {
+            args.push(format!("--s3-endpoint={}", endpoint));
+        }
+        if let Ok(region) = env::var("FIZAP_TEST_S3_REGION") {
+            args.push(format!("--s3-region={}", region));
+        }
+        let config = Config {
+            mongodb_uri: String::new(),
+            jwt_secret: String::new(),
+            jwt_expiration: 0,
+            s3_access_key: env::var("S3_ACCESS_KEY").ok(),
+            s3_secret_key: env::var("S3_SECRET_KEY").ok(),
+            master_key: None,
+            admins: vec![],
+        };
+        let storage = S3Storage::new(&Opt::parse_from(args), &config).unwrap();
+        check_contract(&storage, &format!("test-{}", Uuid::new_v4())).await;
+    }
+}
@@ -0,0 +1,556 @@
+use std::{
+    ffi::OsString,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use futures_util::{stream, StreamExt};
+use tokio::{fs, io::AsyncWriteExt};
+use uuid::Uuid;
+
+use super::{ByteStream, ObjectInfo, Storage};
+use crate::{
+    keys::DataKeys,
+    staging::{StagedFile, StagingKey},
+};
+
+const MAGIC: &[u8; 8] = b"FZENC\x00\x01\x00";
+const PREFIX_LENGTH: usize = 16;
+const HEADER_LENGTH: u64 = 32;
+const CHUNK_SIZE: u64 = 64 * 1024;
+const TAG_LENGTH: u64 = 16;
+const LAST_CHUNK: u64 = 1 << 63;
+
+/// Objects are sealed in fixed size chunks after a header holding the
+/// plaintext size, so a byte range maps to a range of chunks. Each chunk nonce
+/// carries its index and whether it is the last one, which stops chunks from
+/// being reordered or the object from being truncated.
+#[derive(Debug, Clone, Copy)]
+struct SealedHeader {
+    prefix: [u8; PREFIX_LENGTH],
+    size: u64,
+}
+
+impl SealedHeader {
+    fn new(size: u64) -> Self {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut prefix = [0; PREFIX_LENGTH];
+        prefix.copy_from_slice(&nonce[..PREFIX_LENGTH]);
+        SealedHeader { prefix, size }
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LENGTH as usize || !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        let mut prefix = [0; PREFIX_LENGTH];
+        prefix.copy_from_slice(&bytes[8..24]);
+        let size = u64::from_be_bytes(bytes[24..32].try_into().ok()?);
+        Some(SealedHeader { prefix, size })
+    }
+
+    fn encode(&self) -> [u8; HEADER_LENGTH as usize] {
+        let mut bytes = [0; HEADER_LENGTH as usize];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..24].copy_from_slice(&self.prefix);
+        bytes[24..].copy_from_slice(&self.size.to_be_bytes());
+        bytes
+    }
+
+    /// The last chunk is always partial, and empty when the size is a multiple
+    /// of the chunk size.
+    fn chunk_count(&self) -> u64 {
+        self.size / CHUNK_SIZE + 1
+    }
+
+    fn chunk_length(&self, index: u64) -> u64 {
+        if index + 1 == self.chunk_count() {
+            self.size % CHUNK_SIZE
+        } else {
+            CHUNK_SIZE
+        }
+    }
+
+    fn sealed_offset(&self, index: u64) -> u64 {
+        HEADER_LENGTH + index * (CHUNK_SIZE + TAG_LENGTH)
+    }
+
+    fn sealed_size(&self) -> u64 {
+        HEADER_LENGTH + self.size + self.chunk_count() * TAG_LENGTH
+    }
+
+    fn nonce(&self, index: u64) -> XNonce {
+        let counter = if index + 1 == self.chunk_count() {
+            index | LAST_CHUNK
+        } else {
+            index
+        };
+        let mut nonce = XNonce::default();
+        nonce[..PREFIX_LENGTH].copy_from_slice(&self.prefix);
+        nonce[PREFIX_LENGTH..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    fn seal(&self, cipher: &XChaCha20Poly1305, index: u64, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let header = self.encode();
+        let payload = Payload {
+            msg: chunk,
+            aad: &header,
+        };
+        cipher
+            .encrypt(&self.nonce(index), payload)
+            .map_err(|_| io::Error::other("Failed to encrypt object"))
+    }
+
+    fn open(&self, cipher: &XChaCha20Poly1305, index: u64, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let header = self.encode();
+        let payload = Payload {
+            msg: chunk,
+            aad: &header,
+        };
+        cipher
+            .decrypt(&self.nonce(index), payload)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Encrypted object is corrupted"))
+    }
+}
+
+struct OpenState {
+    sealed: ByteStream,
+    buffer: Vec<u8>,
+    cipher: XChaCha20Poly1305,
+    header: SealedHeader,
+    index: u64,
+    skip: usize,
+    remaining: u64,
+}
+
+fn open_stream(state: OpenState) -> ByteStream {
+    stream::try_unfold(state, |mut state| async move {
+        if state.remaining == 0 {
+            return Ok(None);
+        }
+        let length = (state.header.chunk_length(state.index) + TAG_LENGTH) as usize;
+        while state.buffer.len() < length {
+            match state.sealed.next().await {
+                Some(chunk) => state.buffer.extend_from_slice(&chunk?),
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Encrypted object is truncated",
+                    ))
+                }
+            }
+        }
+        let sealed: Vec<u8> = state.buffer.drain(..length).collect();
+        let plain = state.header.open(&state.cipher, state.index, &sealed)?;
+        let from = std::mem::take(&mut state.skip);
+        let to = (plain.len() as u64).min(from as u64 + state.remaining) as usize;
+        state.remaining -= (to - from) as u64;
+        state.index += 1;
+        Ok(Some((Bytes::from(plain).slice(from..to), state)))
+    })
+    .boxed_local()
+}
+
+/// Which data key an object is sealed with. Deduplicated blobs are shared
+/// between accounts, so they all share one `blobs` key instead of an owner's.
+/// Losing that key exposes every deduplicated file, which is the price of
+/// deduplicating across accounts; leave `--dedup` off to keep every file
+/// under its owner's key. Quarantined objects keep
+/// the key of their original location, so they can be moved without reading them.
+fn key_owner(key: &str) -> &str {
+    let mut segments = key.split('/');
+    match segments.next() {
+        Some("quarantine") => key_owner(key.split_once('/').map_or("", |(_, rest)| rest)),
+        Some("versions") | Some("thumbnails") => segments.next().unwrap_or_default(),
+        Some("blobs") => "blobs",
+        Some(owner) => owner,
+        None => "",
+    }
+}
+
+/// Encrypts objects before they reach `inner`. Objects written before
+/// encryption was enabled have no header and are read back as they are.
+#[derive(Debug)]
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    keys: DataKeys,
+    staging_key: StagingKey,
+    staging: PathBuf,
+}
+
+impl EncryptedStorage {
+    pub fn new(
+        inner: Arc<dyn Storage>,
+        keys: DataKeys,
+        staging_key: StagingKey,
+        staging: &str,
+    ) -> Self {
+        EncryptedStorage {
+            inner,
+            keys,
+            staging_key,
+            staging: PathBuf::from(staging),
+        }
+    }
+
+    async fn cipher(&self, key: &str) -> io::Result<XChaCha20Poly1305> {
+        let data_key = self.keys.get(key_owner(key)).await?;
+        Ok(XChaCha20Poly1305::new(&data_key))
+    }
+
+    async fn header(&self, key: &str) -> io::Result<Option<SealedHeader>> {
+        let mut content = self.inner.read(key, 0, HEADER_LENGTH).await?;
+        let mut buffer = Vec::with_capacity(HEADER_LENGTH as usize);
+        while buffer.len() < HEADER_LENGTH as usize {
+            match content.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                // Some backends fail instead of stopping early when an object
+                // is shorter than the header, which only plaintext ones can be.
+                Some(Err(_)) if !buffer.is_empty() => break,
+                Some(Err(e)) => return Err(e),
+                None => break,
+            }
+        }
+        Ok(SealedHeader::parse(&buffer))
+    }
+
+    async fn seal_file(&self, key: &str, source: &Path, target: &Path) -> io::Result<()> {
+        let cipher = self.cipher(key).await?;
+        let size = fs::metadata(source).await?.len();
+        let mut input = StagedFile::open(Some(&self.staging_key), source).await?;
+        let header = SealedHeader::new(size);
+        let mut output = fs::File::create(target).await?;
+        output.write_all(&header.encode()).await?;
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        for index in 0..header.chunk_count() {
+            let chunk = &mut buffer[..header.chunk_length(index) as usize];
+            input.read_exact(chunk).await?;
+            output
+                .write_all(&header.seal(&cipher, index, chunk)?)
+                .await?;
+        }
+        output.flush().await
+    }
+
+    /// Copies an object to an owner with a different data key.
+    async fn reseal(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.staging).await?;
+        let path = self.staging.join(format!("reseal-{}", Uuid::new_v4()));
+        let result = async {
+            let mut content = self.read(from, 0, u64::MAX).await?;
+            let mut file = StagedFile::create(Some(&self.staging_key), &path).await?;
+            while let Some(chunk) = content.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            self.put_file(to, &path).await
+        }
+        .await;
+        if result.is_err() {
+            fs::remove_file(&path).await.ok();
+        }
+        result
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for EncryptedStorage {
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let mut target = OsString::from(source);
+        target.push(".sealed");
+        let target = PathBuf::from(target);
+        let result = match self.seal_file(key, source, &target).await {
+            Ok(()) => self.inner.put_file(key, &target).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            fs::remove_file(&target).await.ok();
+            return result;
+        }
+        fs::remove_file(source).await
+    }
+
+    async fn read(&self, key: &str, start: u64, length: u64) -> io::Result<ByteStream> {
+        let header = match self.header(key).await? {
+            Some(header) => header,
+            None => return self.inner.read(key, start, length).await,
+        };
+        let start = start.min(header.size);
+        let end = start.saturating_add(length).min(header.size);
+        if start == end {
+            return Ok(stream::empty().boxed_local());
+        }
+        let first = start / CHUNK_SIZE;
+        let last = (end - 1) / CHUNK_SIZE;
+        let offset = header.sealed_offset(first);
+        let sealed_end = header.sealed_offset(last) + header.chunk_length(last) + TAG_LENGTH;
+        let sealed = self.inner.read(key, offset, sealed_end - offset).await?;
+        Ok(open_stream(OpenState {
+            sealed,
+            buffer: vec![],
+            cipher: self.cipher(key).await?,
+            header,
+            index: first,
+            skip: (start - first * CHUNK_SIZE) as usize,
+            remaining: end - start,
+        }))
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        let content = self.inner.get(key).await?;
+        let header = match SealedHeader::parse(&content) {
+            Some(header) => header,
+            None => return Ok(content),
+        };
+        if content.len() as u64 != header.sealed_size() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Encrypted object has the wrong size",
+            ));
+        }
+        let cipher = self.cipher(key).await?;
+        let mut plain = Vec::with_capacity(header.size as usize);
+        for index in 0..header.chunk_count() {
+            let start = header.sealed_offset(index) as usize;
+            let end = start + (header.chunk_length(index) + TAG_LENGTH) as usize;
+            plain.extend(header.open(&cipher, index, &content[start..end])?);
+        }
+        Ok(Bytes::from(plain))
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        let cipher = self.cipher(key).await?;
+        let header = SealedHeader::new(content.len() as u64);
+        let mut sealed = Vec::with_capacity(header.sealed_size() as usize);
+        sealed.extend_from_slice(&header.encode());
+        for index in 0..header.chunk_count() {
+            let start = (index * CHUNK_SIZE) as usize;
+            let end = start + header.chunk_length(index) as usize;
+            sealed.extend(header.seal(&cipher, index, &content[start..end])?);
+        }
+        self.inner.put(key, Bytes::from(sealed)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        if key_owner(from) == key_owner(to) || self.header(from).await?.is_none() {
+            return self.inner.copy(from, to).await;
+        }
+        self.reseal(from, to).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        if key_owner(from) == key_owner(to) {
+            return self.inner.rename(from, to).await;
+        }
+        self.copy(from, to).await?;
+        self.inner.delete(from).await
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        self.inner.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use futures_util::TryStreamExt;
+    use mongodb::{options::ClientOptions, Client};
+
+    use super::*;
+    use crate::{keys::KeyRecord, storage::memory::MemoryStorage};
+
+    const SIZE: u64 = 2 * CHUNK_SIZE + 100;
+
+    async fn storage() -> (EncryptedStorage, Arc<MemoryStorage>) {
+        // Never connects, since every key it needs is preloaded.
+        let options = ClientOptions::parse("mongodb://localhost:27017")
+            .await
+            .unwrap();
+        let keys = DataKeys::new(
+            Client::with_options(options)
+                .unwrap()
+                .database("test")
+                .collection::<KeyRecord>("keys"),
+            XChaCha20Poly1305::generate_key(&mut OsRng),
+        );
+        for owner in ["alice", "bob"] {
+            keys.preload(owner, XChaCha20Poly1305::generate_key(&mut OsRng));
+        }
+        let inner = Arc::new(MemoryStorage::default());
+        let storage = EncryptedStorage::new(
+            inner.clone(),
+            keys,
+            StagingKey::generate(),
+            env::temp_dir().to_str().unwrap(),
+        );
+        (storage, inner)
+    }
+
+    fn content(size: u64) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn read_all(
+        storage: &dyn Storage,
+        key: &str,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Vec<u8>> {
+        let chunks: Vec<Bytes> = storage
+            .read(key, start, length)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(chunks.concat())
+    }
+
+    #[test]
+    fn maps_chunks_to_sealed_offsets() {
+        let header = SealedHeader::new(SIZE);
+        assert_eq!(header.chunk_count(), 3);
+        assert_eq!(header.chunk_length(0), CHUNK_SIZE);
+        assert_eq!(header.chunk_length(2), 100);
+        assert_eq!(header.sealed_offset(0), HEADER_LENGTH);
+        assert_eq!(
+            header.sealed_offset(2),
+            HEADER_LENGTH + 2 * (CHUNK_SIZE + TAG_LENGTH)
+        );
+        assert_eq!(header.sealed_size(), HEADER_LENGTH + SIZE + 3 * TAG_LENGTH);
+
+        // A size on a chunk boundary still ends with a chunk, an empty one.
+        let header = SealedHeader::new(2 * CHUNK_SIZE);
+        assert_eq!(header.chunk_count(), 3);
+        assert_eq!(header.chunk_length(2), 0);
+    }
+
+    #[tokio::test]
+    async fn reads_any_range_across_chunks() {
+        let (storage, inner) = storage().await;
+        let plain = content(SIZE);
+        storage
+            .put("alice/file", Bytes::from(plain.clone()))
+            .await
+            .unwrap();
+
+        let sealed = inner.get("alice/file").await.unwrap();
+        assert_eq!(sealed.len() as u64, SealedHeader::new(SIZE).sealed_size());
+        assert!(!sealed
+            .windows(64)
+            .any(|window| window == &plain[1000..1064]));
+
+        let ranges = [
+            (0, u64::MAX),
+            (0, 1),
+            (CHUNK_SIZE - 10, 20),
+            (CHUNK_SIZE, CHUNK_SIZE),
+            (CHUNK_SIZE - 1, CHUNK_SIZE + 2),
+            (2 * CHUNK_SIZE + 50, 1000),
+            (SIZE, 10),
+        ];
+        for (start, length) in ranges {
+            let end = start.saturating_add(length).min(SIZE);
+            let read = read_all(&storage, "alice/file", start, length)
+                .await
+                .unwrap();
+            assert_eq!(
+                read,
+                &plain[start as usize..end as usize],
+                "{}+{}",
+                start,
+                length
+            );
+        }
+        assert_eq!(storage.get("alice/file").await.unwrap(), plain);
+    }
+
+    #[tokio::test]
+    async fn round_trips_sizes_on_chunk_boundaries() {
+        let (storage, _) = storage().await;
+        for size in [0, 1, CHUNK_SIZE, 2 * CHUNK_SIZE] {
+            let plain = content(size);
+            storage
+                .put("alice/file", Bytes::from(plain.clone()))
+                .await
+                .unwrap();
+            assert_eq!(storage.get("alice/file").await.unwrap(), plain);
+            let read = read_all(&storage, "alice/file", 0, u64::MAX).await.unwrap();
+            assert_eq!(read, plain);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_tampered_or_truncated_objects() {
+        let (storage, inner) = storage().await;
+        storage
+            .put("alice/file", Bytes::from(content(SIZE)))
+            .await
+            .unwrap();
+        let sealed = inner.get("alice/file").await.unwrap();
+
+        let mut tampered = sealed.to_vec();
+        tampered[HEADER_LENGTH as usize + CHUNK_SIZE as usize + 40] ^= 1;
+        inner
+            .put("alice/file", Bytes::from(tampered))
+            .await
+            .unwrap();
+        let error = read_all(&storage, "alice/file", CHUNK_SIZE + 10, 10)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(storage.get("alice/file").await.is_err());
+
+        let truncated = sealed.slice(..SealedHeader::new(2 * CHUNK_SIZE).sealed_offset(2) as usize);
+        inner.put("alice/file", truncated).await.unwrap();
+        assert!(read_all(&storage, "alice/file", 0, u64::MAX).await.is_err());
+        assert!(storage.get("alice/file").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn seals_staged_files_and_reseals_across_owners() {
+        let (storage, inner) = storage().await;
+        let plain = content(CHUNK_SIZE + 7);
+        let source = env::temp_dir().join(format!("fizap-sealed-{}", Uuid::new_v4()));
+        let mut staged = StagedFile::create(Some(&storage.staging_key), &source)
+            .await
+            .unwrap();
+        staged.write_all(&plain).await.unwrap();
+        staged.flush().await.unwrap();
+        assert_ne!(std::fs::read(&source).unwrap(), plain);
+
+        storage.put_file("alice/file", &source).await.unwrap();
+        assert!(!source.exists());
+        assert_eq!(storage.get("alice/file").await.unwrap(), plain);
+
+        storage.copy("alice/file", "bob/file").await.unwrap();
+        assert_eq!(storage.get("bob/file").await.unwrap(), plain);
+        assert_ne!(
+            inner.get("alice/file").await.unwrap(),
+            inner.get("bob/file").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_objects_written_before_encryption() {
+        let (storage, inner) = storage().await;
+        inner
+            .put("alice/old", Bytes::from_static(b"plain"))
+            .await
+            .unwrap();
+        assert_eq!(&storage.get("alice/old").await.unwrap()[..], b"plain");
+        assert_eq!(read_all(&storage, "alice/old", 1, 3).await.unwrap(), b"lai");
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use super::{ByteStream, ObjectInfo, Storage};
+
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> Self {
+        LocalStorage {
+            root: PathBuf::from(root),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    async fn prepare(&self, key: &str) -> io::Result<PathBuf> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(path)
+    }
+
+    async fn remove_empty_parents(&self, path: &Path) {
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == self.root || fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+            current = dir.parent();
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for LocalStorage {
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let path = self.prepare(key).await?;
+        if fs::rename(source, &path).await.is_err() {
+            fs::copy(source, &path).await?;
+            fs::remove_file(source).await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self, key: &str, start: u64, length: u64) -> io::Result<ByteStream> {
+        let mut file = fs::File::open(self.path(key)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(ReaderStream::new(file.take(length)).boxed_local())
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        Ok(Bytes::from(fs::read(self.path(key)).await?))
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        let path = self.prepare(key).await?;
+        fs::write(path, content).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        let path = self.prepare(to).await?;
+        fs::copy(self.path(from), path).await?;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let path = self.prepare(to).await?;
+        let source = self.path(from);
+        fs::rename(&source, path).await?;
+        self.remove_empty_parents(&source).await;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let mut found = vec![];
+        let mut pending = vec![self.path(prefix)];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                } else if file_type.is_file() {
+                    let metadata = entry.metadata().await?;
+                    let key = entry
+                        .path()
+                        .strip_prefix(&self.root)
+                        .map_err(|_| io::Error::from(ErrorKind::InvalidData))?
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    found.push(ObjectInfo {
+                        key,
+                        size: metadata.len(),
+                        modified: metadata.modified()?,
+                    });
+                }
+            }
+        }
+        Ok(found)
+    }
+}
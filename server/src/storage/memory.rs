@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use tokio::fs;
+
+use super::{ByteStream, ObjectInfo, Storage};
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<HashMap<String, (Bytes, SystemTime)>>,
+}
+
+impl MemoryStorage {
+    fn object(&self, key: &str) -> io::Result<Bytes> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(content, _)| content.clone())
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))
+    }
+
+    fn insert(&self, key: &str, content: Bytes) {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), (content, SystemTime::now()));
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for MemoryStorage {
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let content = fs::read(source).await?;
+        self.insert(key, Bytes::from(content));
+        fs::remove_file(source).await
+    }
+
+    async fn read(&self, key: &str, start: u64, length: u64) -> io::Result<ByteStream> {
+        let content = self.object(key)?;
+        let start = (start as usize).min(content.len());
+        let end = start.saturating_add(length as usize).min(content.len());
+        Ok(stream::once(async move { Ok(content.slice(start..end)) }).boxed_local())
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        self.object(key)
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        self.insert(key, content);
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        let content = self.object(from)?;
+        self.insert(to, content);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects
+            .remove(from)
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        objects.insert(to.to_owned(), object);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (content, modified))| ObjectInfo {
+                key: key.clone(),
+                size: content.len() as u64,
+                modified: *modified,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+    time::SystemTime,
+};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures_util::{stream, StreamExt};
+use s3::{creds::Credentials, error::S3Error, Bucket, Region};
+use tokio::fs;
+
+use super::{ByteStream, ObjectInfo, Storage};
+use crate::utils::{Config, Opt};
+
+const READ_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    pub fn new(opt: &Opt, config: &Config) -> Result<Self, S3Error> {
+        let name = opt.s3_bucket.as_deref().unwrap_or("fizap");
+        let region = match &opt.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: opt.s3_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => opt.s3_region.parse()?,
+        };
+        let credentials = Credentials::new(
+            config.s3_access_key.as_deref(),
+            config.s3_secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )?;
+        let bucket = Bucket::new(name, region, credentials)?;
+        Ok(S3Storage {
+            bucket: if opt.s3_endpoint.is_some() {
+                bucket.with_path_style()
+            } else {
+                bucket
+            },
+        })
+    }
+}
+
+fn into_io_error(e: S3Error) -> io::Error {
+    match e {
+        S3Error::Http(404, _) => io::Error::from(ErrorKind::NotFound),
+        e => io::Error::other(e),
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for S3Storage {
+    async fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let mut file = fs::File::open(source).await?;
+        self.bucket
+            .put_object_stream(&mut file, key)
+            .await
+            .map_err(into_io_error)?;
+        fs::remove_file(source).await
+    }
+
+    async fn read(&self, key: &str, start: u64, length: u64) -> io::Result<ByteStream> {
+        let bucket = self.bucket.clone();
+        let key = key.to_owned();
+        let end = start.saturating_add(length);
+        Ok(stream::try_unfold(start, move |offset| {
+            let bucket = bucket.clone();
+            let key = key.clone();
+            async move {
+                if offset >= end {
+                    return Ok(None);
+                }
+                let wanted = READ_CHUNK_SIZE.min(end - offset);
+                let response = match bucket
+                    .get_object_range(&key, offset, Some(offset + wanted.max(2) - 1))
+                    .await
+                {
+                    Ok(response) => response,
+                    // Reads may ask for more than is left, like the other
+                    // backends, and stop at the end of the object.
+                    Err(S3Error::Http(416, _)) => return Ok(None),
+                    Err(e) => return Err(into_io_error(e)),
+                };
+                let chunk = response.bytes().clone();
+                if chunk.is_empty() {
+                    return Err(io::Error::from(ErrorKind::UnexpectedEof));
+                }
+                let chunk = chunk.slice(..(wanted as usize).min(chunk.len()));
+                let next = offset + chunk.len() as u64;
+                Ok(Some((chunk, next)))
+            }
+        })
+        .boxed_local())
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        let response = self.bucket.get_object(key).await.map_err(into_io_error)?;
+        Ok(response.bytes().clone())
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> io::Result<()> {
+        self.bucket
+            .put_object(key, &content)
+            .await
+            .map_err(into_io_error)?;
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        self.bucket
+            .copy_object_internal(from, to)
+            .await
+            .map_err(into_io_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.bucket
+            .delete_object(key)
+            .await
+            .map_err(into_io_error)?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let pages = self
+            .bucket
+            .list(prefix.to_owned(), None)
+            .await
+            .map_err(into_io_error)?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| ObjectInfo {
+                modified: DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map(SystemTime::from)
+                    .unwrap_or_else(|_| SystemTime::now()),
+                key: object.key,
+                size: object.size,
+            })
+            .collect())
+    }
+}
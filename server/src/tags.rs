@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    Collection, IndexModel,
+};
+use serde::Serialize;
+
+use crate::{
+    metadata::FileRecord,
+    utils::{AppState, CustomError},
+};
+
+pub const MAX_TAGS: usize = 50;
+pub const MAX_PROPERTIES: usize = 50;
+const MAX_TAG_LENGTH: usize = 64;
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_VALUE_LENGTH: usize = 1024;
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    tag: String,
+    count: u64,
+}
+
+pub async fn create_indexes(files: &Collection<FileRecord>) -> mongodb::error::Result<()> {
+    files
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"owner": 1, "tags": 1})
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Tags are matched exactly, so only surrounding whitespace is dropped. Commas
+/// separate tags in listing filters and can't be part of one.
+pub fn validate_tag(tag: &str) -> Result<&str, CustomError> {
+    let tag = tag.trim();
+    if tag.is_empty()
+        || tag.chars().count() > MAX_TAG_LENGTH
+        || tag.chars().any(|c| c == ',' || c.is_control())
+    {
+        return Err(CustomError::InvalidTag);
+    }
+    Ok(tag)
+}
+
+pub fn validate_tags(tags: &[String]) -> Result<Vec<String>, CustomError> {
+    let mut valid: Vec<String> = vec![];
+    for tag in tags {
+        let tag = validate_tag(tag)?;
+        if !valid.iter().any(|existing| existing == tag) {
+            valid.push(tag.to_owned());
+        }
+    }
+    Ok(valid)
+}
+
+/// Property keys become field names in MongoDB, so they can't contain dots or
+/// start with a dollar sign.
+pub fn validate_property(key: &str, value: &str) -> Result<(), CustomError> {
+    if key.trim() != key
+        || key.is_empty()
+        || key.chars().count() > MAX_KEY_LENGTH
+        || key.starts_with('$')
+        || key.chars().any(|c| c == '.' || c.is_control())
+        || value.chars().count() > MAX_VALUE_LENGTH
+    {
+        return Err(CustomError::InvalidTag);
+    }
+    Ok(())
+}
+
+pub fn validate_properties(properties: &BTreeMap<String, String>) -> Result<(), CustomError> {
+    properties
+        .iter()
+        .try_for_each(|(key, value)| validate_property(key, value))
+}
+
+/// Matches entries carrying every tag in a comma separated list.
+pub fn tag_filter(tags: &str) -> Result<Option<Document>, CustomError> {
+    let tags = tags
+        .split(',')
+        .filter(|tag| !tag.trim().is_empty())
+        .map(|tag| validate_tag(tag).map(|tag| Bson::String(tag.to_owned())))
+        .collect::<Result<Vec<_>, _>>()?;
+    if tags.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(doc! {"tags": {"$all": tags}}))
+    }
+}
+
+pub async fn tag_counts(data: &AppState, owner: &str) -> Result<Vec<TagCount>, CustomError> {
+    let groups: Vec<Document> = data
+        .file_collection
+        .clone_with_type::<Document>()
+        .aggregate(
+            vec![
+                doc! {"$match": {"owner": owner, "trashed": null, "tags.0": {"$exists": true}}},
+                doc! {"$unwind": "$tags"},
+                doc! {"$group": {"_id": "$tags", "count": {"$sum": 1}}},
+                doc! {"$sort": {"count": -1, "_id": 1}},
+            ],
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    Ok(groups
+        .iter()
+        .map(|group| TagCount {
+            tag: group.get_str("_id").unwrap_or_default().to_owned(),
+            count: match group.get("count") {
+                Some(Bson::Int32(count)) => *count as u64,
+                Some(Bson::Int64(count)) => *count as u64,
+                _ => 0,
+            },
+        })
+        .collect())
+}
@@ -0,0 +1,351 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::web::{self, Bytes};
+use futures_util::TryStreamExt;
+use mongodb::{bson::doc, Collection};
+use tantivy::{
+    collector::{Count, TopDocs},
+    directory::MmapDirectory,
+    query::{BooleanQuery, Occur, Query, QueryParser, TermQuery},
+    schema::{
+        Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, STORED, STRING,
+        TEXT,
+    },
+    snippet::SnippetGenerator,
+    Index, IndexReader, IndexWriter, TantivyDocument, Term,
+};
+
+use crate::{
+    detect::Category,
+    metadata::{EntryKind, FileRecord},
+    storage::Storage,
+    utils::{AppState, CustomError},
+};
+
+const MAX_INDEXED_BYTES: u64 = 1024 * 1024;
+const WRITER_MEMORY: usize = 50 * 1024 * 1024;
+const SNIPPET_LENGTH: usize = 200;
+/// How much text is read before it's written to the index in one commit.
+const BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+pub struct TextIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    id: Field,
+    owner: Field,
+    name: Field,
+    body: Field,
+}
+
+impl fmt::Debug for TextIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextIndex").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct TextHit {
+    pub id: String,
+    pub score: f32,
+}
+
+/// One page of text search results, along with what's needed to highlight
+/// the matches in each file. The index doesn't keep a copy of the text, so
+/// snippets are made from the file itself.
+pub struct TextHits {
+    pub hits: Vec<TextHit>,
+    pub total: usize,
+    snippets: SnippetGenerator,
+}
+
+impl TextHits {
+    pub fn snippet(&self, body: &str) -> String {
+        self.snippets.snippet(body).to_html()
+    }
+}
+
+impl TextIndex {
+    /// Opens the index in `dir`. The index itself is never encrypted, so with
+    /// `encrypted` set, file bodies are indexed without word positions.
+    pub fn open(dir: &str, encrypted: bool) -> tantivy::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut schema = Schema::builder();
+        let id = schema.add_text_field("id", STRING | STORED);
+        let owner = schema.add_text_field("owner", STRING);
+        let name = schema.add_text_field("name", TEXT);
+        // The body is indexed but never stored. Word positions would still let
+        // most of a file be pieced back together from the index, so they're
+        // left out when encryption at rest is on, at the cost of phrase
+        // queries. What's left still shows every word in the indexed files,
+        // and which files contain it how often, to anyone who can read the
+        // index directory.
+        let body_indexing = TextFieldIndexing::default()
+            .set_tokenizer("default")
+            .set_index_option(if encrypted {
+                IndexRecordOption::WithFreqs
+            } else {
+                IndexRecordOption::WithFreqsAndPositions
+            });
+        let body = schema.add_text_field(
+            "body",
+            TextOptions::default().set_indexing_options(body_indexing),
+        );
+        let schema = schema.build();
+
+        // An index written with another schema, such as one that stored the
+        // body or was written before encryption was turned on, is thrown
+        // away. It's then empty and gets rebuilt on startup.
+        let directory = MmapDirectory::open(dir)?;
+        if Index::exists(&directory)? && Index::open(directory.clone())?.schema() != schema {
+            std::fs::remove_dir_all(dir)?;
+            std::fs::create_dir_all(dir)?;
+        }
+        let directory = MmapDirectory::open(dir)?;
+        let index = Index::open_or_create(directory, schema)?;
+        let reader = index.reader()?;
+        let writer = Mutex::new(index.writer(WRITER_MEMORY)?);
+
+        Ok(TextIndex {
+            index,
+            reader,
+            writer,
+            id,
+            owner,
+            name,
+            body,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reader.searcher().num_docs() == 0
+    }
+
+    fn commit(&self, writer: &mut IndexWriter) -> tantivy::Result<()> {
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    fn add(&self, writer: &IndexWriter, record: &FileRecord, body: &str) -> tantivy::Result<()> {
+        let id = record.id.to_hex();
+        let mut document = TantivyDocument::default();
+        document.add_text(self.id, &id);
+        document.add_text(self.owner, &record.owner);
+        document.add_text(self.name, &record.name);
+        document.add_text(self.body, body);
+
+        writer.delete_term(Term::from_field_text(self.id, &id));
+        writer.add_document(document)?;
+        Ok(())
+    }
+
+    pub fn upsert(&self, record: &FileRecord, body: &str) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.add(&writer, record, body)?;
+        self.commit(&mut writer)
+    }
+
+    /// Adds or replaces many files at once, with a single commit.
+    pub fn upsert_all(&self, documents: &[(FileRecord, String)]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for (record, body) in documents {
+            self.add(&writer, record, body)?;
+        }
+        self.commit(&mut writer)
+    }
+
+    pub fn remove(&self, ids: &[String]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for id in ids {
+            writer.delete_term(Term::from_field_text(self.id, id));
+        }
+        self.commit(&mut writer)
+    }
+
+    pub fn search(
+        &self,
+        owner: &str,
+        query: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<TextHits, CustomError> {
+        let mut parser = QueryParser::for_index(&self.index, vec![self.name, self.body]);
+        parser.set_conjunction_by_default();
+        let parsed = parser
+            .parse_query(query)
+            .map_err(|_| CustomError::InvalidQuery)?;
+        let owner_query = TermQuery::new(
+            Term::from_field_text(self.owner, owner),
+            IndexRecordOption::Basic,
+        );
+        let scoped = BooleanQuery::new(vec![
+            (Occur::Must, Box::new(owner_query) as Box<dyn Query>),
+            (Occur::Must, parsed.box_clone()),
+        ]);
+
+        let searcher = self.reader.searcher();
+        let (top, total) = searcher
+            .search(
+                &scoped,
+                &(TopDocs::with_limit(limit).and_offset(offset), Count),
+            )
+            .map_err(|_| CustomError::InternalError)?;
+        let mut snippets = SnippetGenerator::create(&searcher, &*parsed, self.body)
+            .map_err(|_| CustomError::InternalError)?;
+        snippets.set_max_num_chars(SNIPPET_LENGTH);
+
+        let mut hits = vec![];
+        for (score, address) in top {
+            let document: TantivyDocument = searcher
+                .doc(address)
+                .map_err(|_| CustomError::InternalError)?;
+            let id = match document.get_first(self.id).and_then(|value| value.as_str()) {
+                Some(id) => id.to_owned(),
+                None => continue,
+            };
+            hits.push(TextHit { id, score });
+        }
+        Ok(TextHits {
+            hits,
+            total,
+            snippets,
+        })
+    }
+}
+
+pub fn is_indexable(record: &FileRecord) -> bool {
+    record.kind == EntryKind::File
+        && (record.mime.starts_with("text/") || record.category == Category::Code)
+}
+
+pub async fn read_text(storage: &dyn Storage, record: &FileRecord) -> std::io::Result<String> {
+    let chunks: Vec<Bytes> = storage
+        .read(&record.content_key(), 0, MAX_INDEXED_BYTES)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(String::from_utf8_lossy(&chunks.concat()).into_owned())
+}
+
+async fn index_with(index: &Arc<TextIndex>, storage: &dyn Storage, record: &FileRecord) {
+    let result = if is_indexable(record) {
+        let body = match read_text(storage, record).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Failed to read {} for indexing: {}", record.id, e);
+                return;
+            }
+        };
+        let index = index.clone();
+        let record = record.clone();
+        web::block(move || index.upsert(&record, &body)).await
+    } else {
+        let index = index.clone();
+        let ids = vec![record.id.to_hex()];
+        web::block(move || index.remove(&ids)).await
+    };
+    match result {
+        Ok(Err(e)) => tracing::warn!("Failed to index {}: {}", record.id, e),
+        Err(e) => tracing::warn!("Failed to index {}: {}", record.id, e),
+        Ok(Ok(())) => {}
+    }
+}
+
+/// Files read for indexing, waiting to be written in one commit.
+#[derive(Default)]
+struct Batch {
+    documents: Vec<(FileRecord, String)>,
+    bytes: usize,
+}
+
+impl Batch {
+    async fn push(&mut self, index: &Arc<TextIndex>, storage: &dyn Storage, record: &FileRecord) {
+        match read_text(storage, record).await {
+            Ok(body) => {
+                self.bytes += body.len();
+                self.documents.push((record.clone(), body));
+            }
+            Err(e) => tracing::warn!("Failed to read {} for indexing: {}", record.id, e),
+        }
+        if self.bytes >= BATCH_BYTES {
+            self.flush(index).await;
+        }
+    }
+
+    async fn flush(&mut self, index: &Arc<TextIndex>) {
+        if self.documents.is_empty() {
+            return;
+        }
+        let documents = std::mem::take(&mut self.documents);
+        let count = documents.len();
+        self.bytes = 0;
+        let index = index.clone();
+        match web::block(move || index.upsert_all(&documents)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("Failed to index {} files: {}", count, e),
+            Err(e) => tracing::warn!("Failed to index {} files: {}", count, e),
+        }
+    }
+}
+
+/// Indexes the text files among `records`, committing them in batches.
+pub async fn index_records(index: &Arc<TextIndex>, storage: &dyn Storage, records: &[FileRecord]) {
+    let mut batch = Batch::default();
+    for record in records.iter().filter(|record| is_indexable(record)) {
+        batch.push(index, storage, record).await;
+    }
+    batch.flush(index).await;
+}
+
+pub async fn index_record(data: &AppState, record: &FileRecord) {
+    index_with(&data.text_index, data.storage.as_ref(), record).await;
+}
+
+pub async fn unindex_record(data: &AppState, record: &FileRecord) {
+    let index = data.text_index.clone();
+    let ids = vec![record.id.to_hex()];
+    match web::block(move || index.remove(&ids)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("Failed to remove {} from the index: {}", record.id, e),
+        Err(e) => tracing::warn!("Failed to remove {} from the index: {}", record.id, e),
+    }
+}
+
+pub async fn rebuild_index(
+    files: Collection<FileRecord>,
+    storage: Arc<dyn Storage>,
+    index: Arc<TextIndex>,
+) {
+    let mut cursor = match files
+        .find(doc! {"kind": "file", "trashed": null}, None)
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            tracing::warn!("Failed to list files for indexing: {}", e);
+            return;
+        }
+    };
+    let mut batch = Batch::default();
+    let mut indexed = 0;
+    loop {
+        let record = match cursor.try_next().await {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Failed to list files for indexing: {}", e);
+                break;
+            }
+        };
+        if is_indexable(&record) {
+            batch.push(&index, storage.as_ref(), &record).await;
+            indexed += 1;
+        }
+    }
+    batch.flush(&index).await;
+    tracing::info!("Indexed {} text files", indexed);
+}
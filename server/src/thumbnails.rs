@@ -0,0 +1,126 @@
+use std::io::Cursor;
+
+use actix_web::web::{self, Bytes};
+use image::{io::Limits, ImageOutputFormat};
+use serde::Deserialize;
+
+use crate::{metadata::FileRecord, storage::Storage, utils::CustomError};
+
+const SUPPORTED_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+const MAX_SOURCE_SIZE: i64 = 64 * 1024 * 1024;
+const MAX_DIMENSION: u32 = 16384;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    fn pixels(&self) -> u32 {
+        match self {
+            ThumbnailSize::Small => 128,
+            ThumbnailSize::Medium => 256,
+            ThumbnailSize::Large => 512,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "small",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Large => "large",
+        }
+    }
+}
+
+pub fn supports_thumbnail(record: &FileRecord) -> bool {
+    SUPPORTED_TYPES.contains(&record.mime.as_str()) && record.size <= MAX_SOURCE_SIZE
+}
+
+fn thumbnail_prefix(record: &FileRecord) -> String {
+    format!("thumbnails/{}/{}/", record.owner, record.id.to_hex())
+}
+
+fn thumbnail_version(record: &FileRecord) -> String {
+    format!("-{}.png", record.modified.timestamp_millis())
+}
+
+fn thumbnail_key(record: &FileRecord, size: ThumbnailSize) -> String {
+    format!(
+        "{}{}{}",
+        thumbnail_prefix(record),
+        size.name(),
+        thumbnail_version(record)
+    )
+}
+
+fn render(source: Bytes, size: ThumbnailSize) -> Result<Vec<u8>, CustomError> {
+    let mut reader = image::io::Reader::new(Cursor::new(source))
+        .with_guessed_format()
+        .map_err(|_| CustomError::UnsupportedMediaType)?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let image = reader
+        .decode()
+        .map_err(|_| CustomError::UnsupportedMediaType)?;
+
+    let mut output = Cursor::new(vec![]);
+    image
+        .thumbnail(size.pixels(), size.pixels())
+        .write_to(&mut output, ImageOutputFormat::Png)
+        .map_err(|_| CustomError::InternalError)?;
+    Ok(output.into_inner())
+}
+
+pub async fn thumbnail(
+    storage: &dyn Storage,
+    record: &FileRecord,
+    size: ThumbnailSize,
+) -> Result<Bytes, CustomError> {
+    if !supports_thumbnail(record) {
+        return Err(CustomError::UnsupportedMediaType);
+    }
+    let key = thumbnail_key(record, size);
+    if let Ok(cached) = storage.get(&key).await {
+        return Ok(cached);
+    }
+
+    let source = storage
+        .get(&record.content_key())
+        .await
+        .map_err(|_| CustomError::MissingPath)?;
+    let rendered = web::block(move || render(source, size))
+        .await
+        .map_err(|_| CustomError::InternalError)??;
+    let rendered = Bytes::from(rendered);
+
+    remove_cached(storage, record, true).await;
+    storage.put(&key, rendered.clone()).await?;
+    Ok(rendered)
+}
+
+async fn remove_cached(storage: &dyn Storage, record: &FileRecord, keep_current: bool) {
+    let cached = match storage.list(&thumbnail_prefix(record)).await {
+        Ok(cached) => cached,
+        Err(e) => {
+            tracing::warn!("Failed to list thumbnails for {}: {}", record.id, e);
+            return;
+        }
+    };
+    let current = thumbnail_version(record);
+    for object in cached {
+        if !(keep_current && object.key.ends_with(&current)) {
+            storage.delete(&object.key).await.ok();
+        }
+    }
+}
+
+pub async fn remove_thumbnails(storage: &dyn Storage, record: &FileRecord) {
+    remove_cached(storage, record, false).await;
+}
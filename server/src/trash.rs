@@ -0,0 +1,175 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime as BsonDateTime},
+    options::FindOptions,
+};
+
+use crate::{
+    metadata::{
+        descendants, folder_path, remove_entry, EntryKind, FileRecord, Target, TrashOrigin,
+    },
+    text_index::{index_record, unindex_record},
+    utils::{AppState, ConflictPolicy, CustomError},
+};
+
+pub async fn trash_entry(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    let files = &data.file_collection;
+    let contents = descendants(files, record).await?;
+    let origin = TrashOrigin {
+        parent: record.parent,
+        path: folder_path(files, &record.owner, record.parent, &mut HashMap::new()).await?,
+    };
+    let origin = mongodb::bson::to_bson(&origin).map_err(|_| CustomError::InternalError)?;
+    let now = BsonDateTime::now();
+
+    let result = files
+        .update_one(
+            doc! {"_id": record.id, "trashed": null},
+            doc! {"$set": {"trashed": now, "trash": origin}},
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(CustomError::MissingPath);
+    }
+    let ids: Vec<_> = contents.iter().map(|child| child.id).collect();
+    files
+        .update_many(
+            doc! {"_id": {"$in": ids}},
+            doc! {"$set": {"trashed": now}},
+            None,
+        )
+        .await?;
+
+    for entry in contents.iter().chain([record]) {
+        if entry.kind == EntryKind::File {
+            unindex_record(data, entry).await;
+        }
+    }
+    Ok(())
+}
+
+pub async fn restore_entry(
+    data: &AppState,
+    mut record: FileRecord,
+    conflict: ConflictPolicy,
+) -> Result<FileRecord, CustomError> {
+    let files = &data.file_collection;
+    let origin = record.trash.clone().ok_or(CustomError::MissingPath)?;
+    let contents = descendants(files, &record).await?;
+
+    // Entries whose folder is gone or still in the trash come back at the root.
+    let parent = match origin.parent {
+        Some(parent) => files
+            .count_documents(
+                doc! {"_id": parent, "owner": &record.owner, "kind": "folder", "trashed": null},
+                None,
+            )
+            .await
+            .map(|count| (count > 0).then_some(parent))?,
+        None => None,
+    };
+    record.name = match conflict
+        .resolve(files, &record.owner, parent, &record.name)
+        .await?
+    {
+        Target::Create(name) => name,
+        Target::Replace(existing) => {
+            if record.kind == EntryKind::Folder {
+                return Err(CustomError::Conflict);
+            }
+            trash_entry(data, &existing).await?;
+            existing.name
+        }
+    };
+    record.parent = parent;
+    record.trashed = None;
+    record.trash = None;
+
+    files
+        .update_one(
+            doc! {"_id": record.id},
+            doc! {
+                "$set": {"parent": record.parent, "name": &record.name},
+                "$unset": {"trashed": "", "trash": ""},
+            },
+            None,
+        )
+        .await?;
+    let ids: Vec<_> = contents.iter().map(|child| child.id).collect();
+    files
+        .update_many(
+            doc! {"_id": {"$in": ids}},
+            doc! {"$unset": {"trashed": ""}},
+            None,
+        )
+        .await?;
+
+    for entry in contents.iter().chain([&record]) {
+        if entry.kind == EntryKind::File {
+            index_record(data, entry).await;
+        }
+    }
+    Ok(record)
+}
+
+pub async fn purge_entry(data: &AppState, record: &FileRecord) -> Result<(), CustomError> {
+    let contents = descendants(&data.file_collection, record).await?;
+    for entry in contents.iter().rev() {
+        remove_entry(data, entry).await?;
+    }
+    remove_entry(data, record).await
+}
+
+pub async fn list_trash(data: &AppState, owner: &str) -> Result<Vec<FileRecord>, CustomError> {
+    let options = FindOptions::builder()
+        .sort(doc! {"trashed": -1, "_id": 1})
+        .build();
+    Ok(data
+        .file_collection
+        .find(doc! {"owner": owner, "trash": {"$ne": null}}, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+async fn purge_expired(data: &AppState, retention: Duration) -> Result<u64, CustomError> {
+    let cutoff = BsonDateTime::from_millis(
+        BsonDateTime::now().timestamp_millis() - retention.as_millis() as i64,
+    );
+    let expired: Vec<FileRecord> = data
+        .file_collection
+        .find(
+            doc! {"trash": {"$ne": null}, "trashed": {"$lt": cutoff}},
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    let mut purged = 0;
+    for record in &expired {
+        purge_entry(data, record).await?;
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+pub async fn expire_trash(data: AppState, retention: u64) {
+    if retention == 0 {
+        return;
+    }
+    let retention = Duration::from_secs(retention);
+    let mut interval = tokio::time::interval(
+        (retention / 4).clamp(Duration::from_secs(60), Duration::from_secs(60 * 60)),
+    );
+    loop {
+        interval.tick().await;
+        match purge_expired(&data, retention).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Purged {} expired trash entries", purged),
+            Err(e) => tracing::warn!("Failed to purge expired trash: {}", e),
+        }
+    }
+}
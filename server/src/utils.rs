@@ -1,19 +1,48 @@
-use std::env;
+use std::{
+    collections::HashSet,
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use actix_web::{HttpResponse, error, http::{header::ContentType, StatusCode}};
-use clap::Parser;
+use actix_web::{
+    error,
+    http::{header::ContentType, StatusCode},
+    HttpResponse,
+};
+use clap::{Parser, Subcommand};
 use derive_more::{Display, Error};
 use mongodb::{
     bson::Document,
+    error::{ErrorKind, WriteFailure},
     options::{ClientOptions, ResolverConfig},
-    Client, Collection,
+    Client, Collection, Database,
+};
+use serde::Deserialize;
+
+use crate::{
+    activity::{self, ActivityRecord},
+    audit::{self, AuditRecord},
+    blobs::{self, BlobRecord},
+    grants::{self, GrantRecord},
+    keys::{parse_master_key, DataKeys, KeyRecord},
+    metadata::{self, FileRecord},
+    shares::{self, ShareRecord},
+    staging::StagingKey,
+    stars::{self, StarRecord},
+    storage::{self, encrypted::EncryptedStorage, Storage, StorageKind},
+    tags,
+    text_index::TextIndex,
+    versions::{self, VersionRecord},
 };
 
+pub const TUS_VERSION: &str = "1.0.0";
+
 #[derive(Parser, Debug, Clone)]
 #[clap(name = "server", about = "A file hosting server")]
 pub struct Opt {
     #[clap(short = 'l', long = "log", default_value = "debug")]
-pub log_level: String,
+    pub log_level: String,
 
     #[clap(short = 'a', long = "addr", default_value = "127.0.0.1")]
     pub addr: String,
@@ -23,6 +52,88 @@ pub log_level: String,
 
     #[clap(long = "static-dir", default_value = "./dist")]
     pub static_dir: String,
+
+    #[clap(long = "max-upload-size", default_value = "1073741824")]
+    pub max_upload_size: u64,
+
+    #[clap(long = "upload-expiration", default_value = "86400")]
+    pub upload_expiration: u64,
+
+    #[clap(long = "storage", value_enum, default_value = "local")]
+    pub storage: StorageKind,
+
+    #[clap(long = "storage-root", default_value = "./files")]
+    pub storage_root: String,
+
+    #[clap(long = "staging-dir", default_value = "./files/.partial")]
+    pub staging_root: String,
+
+    #[clap(long = "s3-bucket")]
+    pub s3_bucket: Option<String>,
+
+    #[clap(long = "s3-region", default_value = "us-east-1")]
+    pub s3_region: String,
+
+    #[clap(long = "s3-endpoint")]
+    pub s3_endpoint: Option<String>,
+
+    /// Store identical files once. Deduplicated files are encrypted with a
+    /// key shared by all accounts rather than their owner's.
+    #[clap(long = "dedup")]
+    pub dedup: bool,
+
+    #[clap(long = "default-quota", default_value = "10737418240")]
+    pub default_quota: u64,
+
+    #[clap(long = "index-dir", default_value = "./files/.index")]
+    pub index_dir: String,
+
+    #[clap(long = "trash-retention", default_value = "2592000")]
+    pub trash_retention: u64,
+
+    #[clap(long = "keep-versions", default_value = "10")]
+    pub keep_versions: usize,
+
+    #[clap(long = "version-retention-days", default_value = "0")]
+    pub version_retention_days: u64,
+
+    #[clap(long = "max-extract-size", default_value = "4294967296")]
+    pub max_extract_size: u64,
+
+    #[clap(long = "max-extract-entries", default_value = "10000")]
+    pub max_extract_entries: usize,
+
+    #[clap(long = "audit-retention-days", default_value = "365")]
+    pub audit_retention_days: u64,
+
+    #[clap(long = "trust-proxy")]
+    pub trust_proxy: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Re-wraps every data key, and the staging key, with the master key in
+    /// NEW_MASTER_KEY, then exits. Restart the server with the new key once it finishes.
+    RotateMasterKey,
+    /// Re-hashes all stored content against the metadata and reports corrupt,
+    /// missing and orphaned files, then exits.
+    Scrub {
+        /// Move corrupt and orphaned objects under `quarantine/`.
+        #[clap(long)]
+        quarantine: bool,
+    },
+    /// Adds records for files copied straight into an account's storage
+    /// directory, then exits. Run it while the server is stopped.
+    ImportUntracked,
+}
+
+impl Opt {
+    pub fn staging_dir(&self, id: &str) -> PathBuf {
+        PathBuf::from(&self.staging_root).join(id)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -30,10 +141,14 @@ pub struct Config {
     pub mongodb_uri: String,
     pub jwt_secret: String,
     pub jwt_expiration: i64,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub master_key: Option<String>,
+    pub admins: Vec<String>,
 }
 
 impl Config {
-    fn init() -> Self {
+    pub fn init() -> Self {
         let mongodb_uri = env::var("MONGODB_URI").expect("No mongodb uri found");
         let jwt_secret = env::var("JWT_SECRET").expect("No json web token secret found");
         let jwt_expiration = env::var("JWT_EXPIRATION")
@@ -44,40 +159,141 @@ impl Config {
             mongodb_uri,
             jwt_secret,
             jwt_expiration,
+            s3_access_key: env::var("S3_ACCESS_KEY").ok(),
+            s3_secret_key: env::var("S3_SECRET_KEY").ok(),
+            master_key: env::var("MASTER_KEY").ok(),
+            admins: env::var("ADMIN_IDS")
+                .map(|ids| {
+                    ids.split(',')
+                        .map(str::trim)
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
         }
     }
+
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.admins.iter().any(|admin| admin == user)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub config: Config,
     pub user_collection: Collection<Document>,
+    pub file_collection: Collection<FileRecord>,
+    pub blob_collection: Collection<BlobRecord>,
+    pub version_collection: Collection<VersionRecord>,
+    pub share_collection: Collection<ShareRecord>,
+    pub grant_collection: Collection<GrantRecord>,
+    pub star_collection: Collection<StarRecord>,
+    pub activity_collection: Collection<ActivityRecord>,
+    pub audit_collection: Collection<AuditRecord>,
     pub opt: Opt,
+    pub active_uploads: Arc<Mutex<HashSet<String>>>,
+    pub storage: Arc<dyn Storage>,
+    pub staging_key: Option<StagingKey>,
+    pub text_index: Arc<TextIndex>,
 }
 
 impl AppState {
     pub async fn init() -> AppState {
         let config = Config::init();
-        let client_options = ClientOptions::parse_with_resolver_config(
-            &config.mongodb_uri,
-            ResolverConfig::cloudflare(),
-        )
-        .await
-        .unwrap();
-        let client = Client::with_options(client_options).unwrap();
-
-        let user_collection = client
-            .database("MuZap")
-            .collection::<mongodb::bson::Document>("users");
+        let database = connect(&config).await;
+
+        let user_collection = database.collection::<mongodb::bson::Document>("users");
+        let file_collection = database.collection::<FileRecord>("files");
+        let blob_collection = database.collection::<BlobRecord>("blobs");
+        let version_collection = database.collection::<VersionRecord>("versions");
+        let share_collection = database.collection::<ShareRecord>("shares");
+        let grant_collection = database.collection::<GrantRecord>("grants");
+        let star_collection = database.collection::<StarRecord>("stars");
+        let activity_collection = database.collection::<ActivityRecord>("activity");
+        let audit_collection = database.collection::<AuditRecord>("audit");
+        metadata::create_indexes(&file_collection).await.unwrap();
+        metadata::backfill_categories(&file_collection)
+            .await
+            .unwrap();
+        blobs::create_indexes(&blob_collection).await.unwrap();
+        versions::create_indexes(&version_collection).await.unwrap();
+        shares::create_indexes(&share_collection).await.unwrap();
+        grants::create_indexes(&grant_collection).await.unwrap();
+        tags::create_indexes(&file_collection).await.unwrap();
+        stars::create_indexes(&star_collection).await.unwrap();
+        activity::create_indexes(&activity_collection)
+            .await
+            .unwrap();
+        audit::create_indexes(&audit_collection).await.unwrap();
         let opt = Opt::parse();
+        let storage = open_storage(&opt, &config, &database);
+        let staging_key = staging_key(&opt, &config);
+        let text_index =
+            Arc::new(TextIndex::open(&opt.index_dir, config.master_key.is_some()).unwrap());
         AppState {
             config,
             user_collection,
+            file_collection,
+            blob_collection,
+            version_collection,
+            share_collection,
+            grant_collection,
+            star_collection,
+            activity_collection,
+            audit_collection,
             opt,
+            active_uploads: Arc::new(Mutex::new(HashSet::new())),
+            storage,
+            staging_key,
+            text_index,
+        }
+    }
+}
+
+/// The configured storage, encrypting content when a master key is set.
+pub fn open_storage(opt: &Opt, config: &Config, database: &Database) -> Arc<dyn Storage> {
+    let storage = storage::init(opt, config);
+    match (master_key(config), staging_key(opt, config)) {
+        (Some(master), Some(staging_key)) => {
+            let keys = DataKeys::new(database.collection::<KeyRecord>("keys"), master);
+            Arc::new(EncryptedStorage::new(
+                storage,
+                keys,
+                staging_key,
+                &opt.staging_root,
+            ))
         }
+        _ => storage,
     }
 }
 
+fn master_key(config: &Config) -> Option<chacha20poly1305::Key> {
+    config.master_key.as_ref().map(|master| {
+        parse_master_key(master).expect("MASTER_KEY must be a base64 encoded 32 byte key")
+    })
+}
+
+/// The key staged files are encrypted with, when encryption at rest is on.
+pub fn staging_key(opt: &Opt, config: &Config) -> Option<StagingKey> {
+    master_key(config).map(|master| {
+        StagingKey::load(&master, Path::new(&opt.staging_root))
+            .expect("Failed to load the staging key")
+    })
+}
+
+pub async fn connect(config: &Config) -> Database {
+    let client_options = ClientOptions::parse_with_resolver_config(
+        &config.mongodb_uri,
+        ResolverConfig::cloudflare(),
+    )
+    .await
+    .unwrap();
+    Client::with_options(client_options)
+        .unwrap()
+        .database("MuZap")
+}
+
 #[derive(Debug, Display, Error)]
 pub enum CustomError {
     #[display(fmt = "Invalid credentials")]
@@ -88,11 +304,63 @@ pub enum CustomError {
     MissingBody,
     #[display(fmt = "Path not found")]
     MissingPath,
+    #[display(fmt = "Invalid file name")]
+    InvalidName,
+    #[display(fmt = "File already exists")]
+    Conflict,
+    #[display(fmt = "File exceeds the maximum upload size")]
+    PayloadTooLarge,
+    #[display(fmt = "Internal server error")]
+    InternalError,
+    #[display(fmt = "Upload offset does not match")]
+    OffsetMismatch,
+    #[display(fmt = "Upload is already in progress")]
+    UploadLocked,
+    #[display(fmt = "Unsupported content type")]
+    UnsupportedMediaType,
+    #[display(fmt = "Unsupported tus version")]
+    UnsupportedVersion,
+    #[display(fmt = "Missing or invalid headers")]
+    InvalidHeaders,
+    #[display(fmt = "Folder is not empty")]
+    FolderNotEmpty,
+    #[display(fmt = "Invalid destination")]
+    InvalidDestination,
+    #[display(fmt = "Invalid cursor")]
+    InvalidCursor,
+    #[display(fmt = "Storage quota exceeded")]
+    QuotaExceeded,
+    #[display(fmt = "Invalid search query")]
+    InvalidQuery,
+    #[display(fmt = "Share link is no longer available")]
+    ShareUnavailable,
+    #[display(fmt = "Password required")]
+    PasswordRequired,
+    #[display(fmt = "Invalid share settings")]
+    InvalidShare,
+    #[display(fmt = "Permission denied")]
+    Forbidden,
+    #[display(fmt = "No account with that email")]
+    UnknownAccount,
+    #[display(fmt = "Invalid grant")]
+    InvalidGrant,
+    #[display(fmt = "Unsupported or invalid archive")]
+    InvalidArchive,
+    #[display(fmt = "Archive exceeds the extraction limits")]
+    ArchiveTooLarge,
+    #[display(fmt = "Invalid tag or property")]
+    InvalidTag,
+    #[display(fmt = "Pruning needs versions to keep or a cutoff date")]
+    InvalidPrune,
 }
 
 impl error::ResponseError for CustomError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
+        let mut response = HttpResponse::build(self.status_code());
+        if let CustomError::UnsupportedVersion = self {
+            response.insert_header(("Tus-Version", TUS_VERSION));
+        }
+        response
             .insert_header(ContentType::html())
             .body(self.to_string())
     }
@@ -103,6 +371,78 @@ impl error::ResponseError for CustomError {
             CustomError::JWTError => StatusCode::UNAUTHORIZED,
             CustomError::MissingBody => StatusCode::BAD_REQUEST,
             CustomError::MissingPath => StatusCode::NOT_FOUND,
+            CustomError::InvalidName => StatusCode::BAD_REQUEST,
+            CustomError::Conflict => StatusCode::CONFLICT,
+            CustomError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            CustomError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            CustomError::OffsetMismatch => StatusCode::CONFLICT,
+            CustomError::UploadLocked => StatusCode::LOCKED,
+            CustomError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            CustomError::UnsupportedVersion => StatusCode::PRECONDITION_FAILED,
+            CustomError::InvalidHeaders => StatusCode::BAD_REQUEST,
+            CustomError::FolderNotEmpty => StatusCode::CONFLICT,
+            CustomError::InvalidDestination => StatusCode::BAD_REQUEST,
+            CustomError::InvalidCursor => StatusCode::BAD_REQUEST,
+            CustomError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            CustomError::InvalidQuery => StatusCode::BAD_REQUEST,
+            CustomError::ShareUnavailable => StatusCode::GONE,
+            CustomError::PasswordRequired => StatusCode::UNAUTHORIZED,
+            CustomError::InvalidShare => StatusCode::BAD_REQUEST,
+            CustomError::Forbidden => StatusCode::FORBIDDEN,
+            CustomError::UnknownAccount => StatusCode::NOT_FOUND,
+            CustomError::InvalidGrant => StatusCode::BAD_REQUEST,
+            CustomError::InvalidArchive => StatusCode::UNPROCESSABLE_ENTITY,
+            CustomError::ArchiveTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            CustomError::InvalidTag => StatusCode::BAD_REQUEST,
+            CustomError::InvalidPrune => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<std::io::Error> for CustomError {
+    fn from(_: std::io::Error) -> Self {
+        CustomError::InternalError
+    }
+}
+
+impl From<mongodb::error::Error> for CustomError {
+    fn from(e: mongodb::error::Error) -> Self {
+        match *e.kind {
+            ErrorKind::Write(WriteFailure::WriteError(ref error)) if error.code == 11000 => {
+                CustomError::Conflict
+            }
+            _ => CustomError::InternalError,
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn validate_file_name(name: &str) -> Result<&str, CustomError> {
+    let name = name.trim();
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.len() > 255
+        || name
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(CustomError::InvalidName);
+    }
+    Ok(name)
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    Fail,
+    Overwrite,
+    Rename,
+}
+
+pub fn numbered_name(name: &str, n: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, extension),
+        _ => format!("{} ({})", name, n),
+    }
+}
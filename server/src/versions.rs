@@ -0,0 +1,291 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    options::FindOptions,
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blobs::{blob_key, release_blob},
+    detect::Category,
+    metadata::{content_key, FileRecord},
+    text_index::index_record,
+    thumbnails::remove_thumbnails,
+    utils::{AppState, CustomError},
+};
+
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A previous state of a file's content, archived when the file was overwritten.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub file: ObjectId,
+    pub owner: String,
+    pub size: i64,
+    pub mime: String,
+    pub modified: BsonDateTime,
+    pub archived: BsonDateTime,
+    pub blob: Option<String>,
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+impl VersionRecord {
+    fn new(record: &FileRecord) -> Self {
+        VersionRecord {
+            id: ObjectId::new(),
+            file: record.id,
+            owner: record.owner.clone(),
+            size: record.size,
+            mime: record.mime.clone(),
+            modified: record.modified,
+            archived: BsonDateTime::now(),
+            blob: record.blob.clone(),
+            checksum: record.checksum.clone(),
+        }
+    }
+
+    pub fn content_key(&self) -> String {
+        match &self.blob {
+            Some(hash) => blob_key(hash),
+            None => format!("versions/{}/{}", self.owner, self.id.to_hex()),
+        }
+    }
+
+    /// The file as it looked when this version was current.
+    pub fn as_record(&self, file: &FileRecord) -> FileRecord {
+        FileRecord {
+            size: self.size,
+            mime: self.mime.clone(),
+            category: Category::from_mime(&self.mime),
+            modified: self.modified,
+            blob: self.blob.clone(),
+            checksum: self.checksum.clone(),
+            ..file.clone()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionMetadata {
+    id: String,
+    size: i64,
+    mime: String,
+    modified: DateTime<Utc>,
+    archived: DateTime<Utc>,
+}
+
+impl From<&VersionRecord> for VersionMetadata {
+    fn from(version: &VersionRecord) -> Self {
+        VersionMetadata {
+            id: version.id.to_hex(),
+            size: version.size,
+            mime: version.mime.clone(),
+            modified: version.modified.to_chrono(),
+            archived: version.archived.to_chrono(),
+        }
+    }
+}
+
+pub async fn create_indexes(versions: &Collection<VersionRecord>) -> mongodb::error::Result<()> {
+    versions
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"file": 1, "archived": -1})
+                .build(),
+            None,
+        )
+        .await?;
+    versions
+        .create_index(
+            IndexModel::builder().keys(doc! {"archived": 1}).build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub fn keeps_versions(data: &AppState) -> bool {
+    data.opt.keep_versions > 0
+}
+
+/// Moves the current content of `record` into a new version. The file record
+/// itself is left untouched, callers are expected to give it new content.
+pub async fn archive_version(
+    data: &AppState,
+    record: &FileRecord,
+) -> Result<VersionRecord, CustomError> {
+    let version = VersionRecord::new(record);
+    if version.blob.is_none() {
+        data.storage
+            .rename(&record.content_key(), &version.content_key())
+            .await?;
+    }
+    if let Err(e) = data.version_collection.insert_one(&version, None).await {
+        unarchive_version(data, &version).await;
+        return Err(e.into());
+    }
+    Ok(version)
+}
+
+/// Undoes `archive_version` when the new content could not be stored.
+pub async fn unarchive_version(data: &AppState, version: &VersionRecord) {
+    data.version_collection
+        .delete_one(doc! {"_id": version.id}, None)
+        .await
+        .ok();
+    if version.blob.is_none() {
+        let key = content_key(&version.owner, version.file);
+        if let Err(e) = data.storage.rename(&version.content_key(), &key).await {
+            tracing::warn!("Failed to restore content of {}: {}", version.file, e);
+        }
+    }
+}
+
+pub async fn find_version(
+    data: &AppState,
+    file: &FileRecord,
+    id: &str,
+) -> Result<VersionRecord, CustomError> {
+    let id = ObjectId::parse_str(id).map_err(|_| CustomError::MissingPath)?;
+    data.version_collection
+        .find_one(
+            doc! {"_id": id, "file": file.id, "owner": &file.owner},
+            None,
+        )
+        .await?
+        .ok_or(CustomError::MissingPath)
+}
+
+pub async fn list_versions(
+    data: &AppState,
+    file: &FileRecord,
+) -> Result<Vec<VersionRecord>, CustomError> {
+    let options = FindOptions::builder()
+        .sort(doc! {"archived": -1, "_id": -1})
+        .build();
+    Ok(data
+        .version_collection
+        .find(doc! {"file": file.id, "owner": &file.owner}, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+async fn remove_version(data: &AppState, version: &VersionRecord) -> Result<(), CustomError> {
+    data.version_collection
+        .delete_one(doc! {"_id": version.id}, None)
+        .await?;
+    match &version.blob {
+        Some(hash) => release_blob(&data.blob_collection, hash).await,
+        None => {
+            data.storage.delete(&version.content_key()).await.ok();
+            Ok(())
+        }
+    }
+}
+
+/// Removes every version beyond the newest `keep`, and any archived before `before`.
+pub async fn prune_versions(
+    data: &AppState,
+    file: &FileRecord,
+    keep: usize,
+    before: Option<BsonDateTime>,
+) -> Result<u64, CustomError> {
+    let mut removed = 0;
+    for (position, version) in list_versions(data, file).await?.iter().enumerate() {
+        let expired = before.is_some_and(|before| version.archived < before);
+        if position >= keep || expired {
+            remove_version(data, version).await?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub async fn remove_versions(data: &AppState, file: &FileRecord) -> Result<(), CustomError> {
+    prune_versions(data, file, 0, None).await.map(|_| ())
+}
+
+pub async fn restore_version(
+    data: &AppState,
+    mut record: FileRecord,
+    version: VersionRecord,
+) -> Result<FileRecord, CustomError> {
+    let previous = record.clone();
+    let archived = archive_version(data, &record).await?;
+    if version.blob.is_none() {
+        let key = content_key(&record.owner, record.id);
+        if let Err(e) = data.storage.rename(&version.content_key(), &key).await {
+            unarchive_version(data, &archived).await;
+            return Err(e.into());
+        }
+    }
+    // The restored version hands its content, and any blob reference, to the file.
+    data.version_collection
+        .delete_one(doc! {"_id": version.id}, None)
+        .await?;
+
+    record.size = version.size;
+    record.mime = version.mime;
+    record.category = Category::from_mime(&record.mime);
+    record.blob = version.blob;
+    record.checksum = version.checksum;
+    record.modified = BsonDateTime::now();
+    data.file_collection
+        .update_one(
+            doc! {"_id": record.id},
+            doc! {"$set": {
+                "size": record.size,
+                "mime": &record.mime,
+                "category": record.category,
+                "modified": record.modified,
+                "blob": &record.blob,
+                "checksum": &record.checksum,
+            }},
+            None,
+        )
+        .await?;
+    remove_thumbnails(data.storage.as_ref(), &previous).await;
+    index_record(data, &record).await;
+    prune_versions(data, &record, data.opt.keep_versions, None).await?;
+    Ok(record)
+}
+
+async fn remove_expired_versions(data: &AppState, retention: Duration) -> Result<u64, CustomError> {
+    let cutoff = BsonDateTime::from_millis(
+        BsonDateTime::now().timestamp_millis() - retention.as_millis() as i64,
+    );
+    let expired: Vec<VersionRecord> = data
+        .version_collection
+        .find(doc! {"archived": {"$lt": cutoff}}, None)
+        .await?
+        .try_collect()
+        .await?;
+    for version in &expired {
+        remove_version(data, version).await?;
+    }
+    Ok(expired.len() as u64)
+}
+
+pub async fn expire_versions(data: AppState, retention_days: u64) {
+    if retention_days == 0 {
+        return;
+    }
+    let retention = Duration::from_secs(retention_days * 24 * 60 * 60);
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        match remove_expired_versions(&data, retention).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("Removed {} expired versions", removed),
+            Err(e) => tracing::warn!("Failed to remove expired versions: {}", e),
+        }
+    }
+}
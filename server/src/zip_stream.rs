@@ -0,0 +1,345 @@
+use std::{collections::VecDeque, io, sync::Arc};
+
+use actix_web::web::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use futures_util::{
+    stream::{self, LocalBoxStream},
+    Stream, StreamExt,
+};
+
+use crate::{
+    download::content_slice,
+    metadata::{EntryKind, FileRecord},
+    storage::Storage,
+};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const END_OF_CENTRAL: u32 = 0x0605_4b50;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Made on unix, so extractors read the external attributes as permissions.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const ZIP64_EXTRA: u16 = 0x0001;
+const FILE_ATTRIBUTES: u32 = 0o100644 << 16;
+const FOLDER_ATTRIBUTES: u32 = (0o040755 << 16) | 0x10;
+
+const U16_LIMIT: u64 = 0xFFFF;
+const U32_LIMIT: u64 = 0xFFFF_FFFF;
+
+/// A file or folder in the archive. Folder paths end with a slash.
+pub struct ZipEntry {
+    pub path: String,
+    pub record: FileRecord,
+}
+
+struct Header<'a> {
+    path: &'a str,
+    flags: u16,
+    time: u16,
+    date: u16,
+    zip64: bool,
+    external: u32,
+}
+
+struct ActiveEntry {
+    content: LocalBoxStream<'static, io::Result<Bytes>>,
+    hasher: Hasher,
+    written: u64,
+    offset: u64,
+    entry: ZipEntry,
+}
+
+struct ZipState {
+    storage: Arc<dyn Storage>,
+    pending: VecDeque<ZipEntry>,
+    active: Option<ActiveEntry>,
+    central: Vec<u8>,
+    count: u64,
+    offset: u64,
+    done: bool,
+}
+
+fn put16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn dos_timestamp(time: DateTime<Utc>) -> (u16, u16) {
+    let year = time.year().clamp(1980, 2107) as u32;
+    let time_part = (time.hour() << 11) | (time.minute() << 5) | (time.second() / 2);
+    let date_part = ((year - 1980) << 9) | (time.month() << 5) | time.day();
+    (time_part as u16, date_part as u16)
+}
+
+impl ZipEntry {
+    fn header(&self) -> Header<'_> {
+        let (time, date) = dos_timestamp(self.record.modified.to_chrono());
+        match self.record.kind {
+            EntryKind::Folder => Header {
+                path: &self.path,
+                flags: FLAG_UTF8,
+                time,
+                date,
+                zip64: false,
+                external: FOLDER_ATTRIBUTES,
+            },
+            EntryKind::File => Header {
+                path: &self.path,
+                flags: FLAG_UTF8 | FLAG_DATA_DESCRIPTOR,
+                time,
+                date,
+                zip64: self.record.size as u64 >= U32_LIMIT,
+                external: FILE_ATTRIBUTES,
+            },
+        }
+    }
+}
+
+impl Header<'_> {
+    /// Files are written with a data descriptor, so the checksum and sizes
+    /// follow the content instead of being known up front.
+    fn local(&self) -> Bytes {
+        let mut buffer = Vec::with_capacity(50 + self.path.len());
+        put32(&mut buffer, LOCAL_HEADER);
+        put16(
+            &mut buffer,
+            if self.zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            },
+        );
+        put16(&mut buffer, self.flags);
+        put16(&mut buffer, 0);
+        put16(&mut buffer, self.time);
+        put16(&mut buffer, self.date);
+        put32(&mut buffer, 0);
+        let placeholder = if self.zip64 { U32_LIMIT as u32 } else { 0 };
+        put32(&mut buffer, placeholder);
+        put32(&mut buffer, placeholder);
+        put16(&mut buffer, self.path.len() as u16);
+        put16(&mut buffer, if self.zip64 { 20 } else { 0 });
+        buffer.extend_from_slice(self.path.as_bytes());
+        if self.zip64 {
+            put16(&mut buffer, ZIP64_EXTRA);
+            put16(&mut buffer, 16);
+            put64(&mut buffer, 0);
+            put64(&mut buffer, 0);
+        }
+        Bytes::from(buffer)
+    }
+
+    fn descriptor(&self, crc: u32, size: u64) -> Bytes {
+        let mut buffer = Vec::with_capacity(24);
+        put32(&mut buffer, DATA_DESCRIPTOR);
+        put32(&mut buffer, crc);
+        if self.zip64 {
+            put64(&mut buffer, size);
+            put64(&mut buffer, size);
+        } else {
+            put32(&mut buffer, size as u32);
+            put32(&mut buffer, size as u32);
+        }
+        Bytes::from(buffer)
+    }
+
+    fn central(&self, buffer: &mut Vec<u8>, crc: u32, size: u64, offset: u64) {
+        let mut extra = vec![];
+        if self.zip64 {
+            put64(&mut extra, size);
+            put64(&mut extra, size);
+        }
+        if offset >= U32_LIMIT {
+            put64(&mut extra, offset);
+        }
+        let version = if extra.is_empty() {
+            VERSION_DEFAULT
+        } else {
+            VERSION_ZIP64
+        };
+
+        put32(buffer, CENTRAL_HEADER);
+        put16(buffer, VERSION_MADE_BY);
+        put16(buffer, version);
+        put16(buffer, self.flags);
+        put16(buffer, 0);
+        put16(buffer, self.time);
+        put16(buffer, self.date);
+        put32(buffer, crc);
+        let size = if self.zip64 { U32_LIMIT } else { size };
+        put32(buffer, size as u32);
+        put32(buffer, size as u32);
+        put16(buffer, self.path.len() as u16);
+        put16(
+            buffer,
+            if extra.is_empty() {
+                0
+            } else {
+                extra.len() as u16 + 4
+            },
+        );
+        put16(buffer, 0);
+        put16(buffer, 0);
+        put16(buffer, 0);
+        put32(buffer, self.external);
+        put32(buffer, offset.min(U32_LIMIT) as u32);
+        buffer.extend_from_slice(self.path.as_bytes());
+        if !extra.is_empty() {
+            put16(buffer, ZIP64_EXTRA);
+            put16(buffer, extra.len() as u16);
+            buffer.extend_from_slice(&extra);
+        }
+    }
+}
+
+impl ZipState {
+    fn start(&mut self, entry: ZipEntry) -> io::Result<Bytes> {
+        let header = entry.header().local();
+        match entry.record.kind {
+            EntryKind::Folder => {
+                entry.header().central(&mut self.central, 0, 0, self.offset);
+                self.count += 1;
+            }
+            EntryKind::File => {
+                let size = entry.record.size as u64;
+                let content = if size == 0 {
+                    stream::empty().boxed_local()
+                } else {
+                    content_slice(self.storage.clone(), entry.record.content_key(), 0, size)
+                        .boxed_local()
+                };
+                self.active = Some(ActiveEntry {
+                    content,
+                    hasher: Hasher::new(),
+                    written: 0,
+                    offset: self.offset,
+                    entry,
+                });
+            }
+        }
+        Ok(header)
+    }
+
+    fn finish(&mut self, active: ActiveEntry) -> io::Result<Bytes> {
+        let header = active.entry.header();
+        if !header.zip64 && active.written >= U32_LIMIT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File is larger than its recorded size",
+            ));
+        }
+        let crc = active.hasher.finalize();
+        header.central(&mut self.central, crc, active.written, active.offset);
+        self.count += 1;
+        Ok(header.descriptor(crc, active.written))
+    }
+
+    fn end(&mut self) -> Bytes {
+        let directory_offset = self.offset;
+        let directory_size = self.central.len() as u64;
+        let mut buffer = std::mem::take(&mut self.central);
+        if self.count >= U16_LIMIT || directory_offset >= U32_LIMIT || directory_size >= U32_LIMIT {
+            put32(&mut buffer, ZIP64_END);
+            put64(&mut buffer, 44);
+            put16(&mut buffer, VERSION_MADE_BY);
+            put16(&mut buffer, VERSION_ZIP64);
+            put32(&mut buffer, 0);
+            put32(&mut buffer, 0);
+            put64(&mut buffer, self.count);
+            put64(&mut buffer, self.count);
+            put64(&mut buffer, directory_size);
+            put64(&mut buffer, directory_offset);
+
+            put32(&mut buffer, ZIP64_LOCATOR);
+            put32(&mut buffer, 0);
+            put64(&mut buffer, directory_offset + directory_size);
+            put32(&mut buffer, 1);
+        }
+        put32(&mut buffer, END_OF_CENTRAL);
+        put16(&mut buffer, 0);
+        put16(&mut buffer, 0);
+        put16(&mut buffer, self.count.min(U16_LIMIT) as u16);
+        put16(&mut buffer, self.count.min(U16_LIMIT) as u16);
+        put32(&mut buffer, directory_size.min(U32_LIMIT) as u32);
+        put32(&mut buffer, directory_offset.min(U32_LIMIT) as u32);
+        put16(&mut buffer, 0);
+        Bytes::from(buffer)
+    }
+
+    async fn next_chunk(&mut self) -> Option<io::Result<Bytes>> {
+        if self.done {
+            return None;
+        }
+        if let Some(active) = &mut self.active {
+            match active.content.next().await {
+                Some(Ok(chunk)) => {
+                    active.hasher.update(&chunk);
+                    active.written += chunk.len() as u64;
+                    return Some(Ok(chunk));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let active = self.active.take()?;
+                    return Some(self.finish(active));
+                }
+            }
+        }
+        match self.pending.pop_front() {
+            Some(entry) => Some(self.start(entry)),
+            None => {
+                self.done = true;
+                Some(Ok(self.end()))
+            }
+        }
+    }
+}
+
+impl Drop for ZipState {
+    fn drop(&mut self) {
+        if !self.done {
+            tracing::debug!("ZIP download stopped after {} bytes", self.offset);
+        }
+    }
+}
+
+/// Streams a ZIP archive of `entries` as it is written, storing content
+/// uncompressed. Dropping the stream, e.g. when the client disconnects, stops
+/// reading from storage.
+pub fn zip_stream(
+    storage: Arc<dyn Storage>,
+    entries: Vec<ZipEntry>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let state = ZipState {
+        storage,
+        pending: entries.into(),
+        active: None,
+        central: vec![],
+        count: 0,
+        offset: 0,
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        let chunk = state.next_chunk().await?;
+        match &chunk {
+            Ok(bytes) => state.offset += bytes.len() as u64,
+            // A broken archive can't be recovered mid-stream, so end it here.
+            Err(_) => state.done = true,
+        }
+        Some((chunk, state))
+    })
+}